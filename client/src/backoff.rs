@@ -0,0 +1,60 @@
+use core::time::Duration;
+
+/// Jittered exponential backoff between reconnect attempts, with no limit on attempts
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Backoff {
+    /// Bound on the first delay, doubled for each further attempt
+    pub base: Duration,
+    /// Bound on any delay
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay before retry `attempt`, counting from 0, for a `random` value in `[0, 1)`.
+    /// Half the bound is fixed and half is jitter, so clients dropped together spread out.
+    pub fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        let bound = self.base.saturating_mul(factor).min(self.max);
+        bound / 2 + (bound / 2).mul_f64(random.clamp(0.0, 1.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(0, 0.0), Duration::from_millis(250));
+        assert_eq!(backoff.delay(1, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff.delay(2, 0.0), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn jitter() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(0, 0.5), Duration::from_millis(375));
+        assert!(backoff.delay(0, 0.999) < Duration::from_millis(500));
+        // out of range randomness is clamped rather than leaving the bound
+        assert_eq!(backoff.delay(0, 2.0), Duration::from_millis(500));
+        assert_eq!(backoff.delay(0, -1.0), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn capped() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(10, 0.0), Duration::from_secs(15));
+        assert_eq!(backoff.delay(10, 1.0), Duration::from_secs(30));
+        assert_eq!(backoff.delay(u32::MAX, 1.0), Duration::from_secs(30));
+    }
+}
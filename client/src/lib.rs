@@ -1 +1,6 @@
-
+pub mod backoff;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+#[cfg(feature = "bevy")]
+pub mod plugin;
+pub mod web;
@@ -0,0 +1,345 @@
+use core::fmt;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Serialize, de::DeserializeOwned};
+use session::{
+    action::Action,
+    fair::Entropy,
+    info::StateInfo,
+    invite::InviteCode,
+    msg::Msg,
+    protocol::{self, ActionReply, BAN_CLOSE_CODE, Envelope, Events, Hello, Kind, Reject},
+    queue::AsQueue,
+    settings::SettingsOf,
+    stream::{STREAM_HEADER_LEN, StreamFrame},
+    token::ClientToken,
+};
+use std::{
+    marker::PhantomData,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// Error from connecting when the server refuses the connection, e.g. for a schema
+/// mismatch or a ban, with its reason. Reconnecting won't help.
+#[derive(Clone, Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+enum Outgoing {
+    Message(Vec<u8>),
+    Pong,
+}
+
+/// Raw envelopes to and from the server, pumped by background tasks
+struct Pipe {
+    send: kanal::AsyncSender<Outgoing>,
+    recv: kanal::AsyncReceiver<Vec<u8>>,
+    /// Reason the server closed the connection with a [`BAN_CLOSE_CODE`], set before
+    /// `recv` closes
+    rejected: Arc<OnceLock<String>>,
+}
+
+async fn ws_pipe(url: &str) -> eyre::Result<Pipe> {
+    let (ws, _) = tokio_tungstenite::connect_async(url).await?;
+    let (mut write, mut read) = ws.split();
+    let (send, outgoing) = kanal::unbounded_async();
+    let (incoming, recv) = kanal::unbounded_async();
+    let rejected = Arc::new(OnceLock::new());
+    let reason = rejected.clone();
+    tokio::spawn(async move {
+        while let Ok(outgoing) = outgoing.recv().await {
+            // pongs are answered by tungstenite itself
+            if let Outgoing::Message(bytes) = outgoing
+                && write.send(WsMessage::Binary(bytes.into())).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = read.next().await {
+            match msg {
+                WsMessage::Binary(bytes) => {
+                    if incoming.send(bytes.to_vec()).await.is_err() {
+                        break;
+                    }
+                }
+                WsMessage::Close(frame) => {
+                    if let Some(frame) = frame
+                        && u16::from(frame.code) == BAN_CLOSE_CODE
+                    {
+                        let _ = reason.set(frame.reason.to_string());
+                    }
+                    break;
+                }
+                _ => {}
+            }
+        }
+    });
+    Ok(Pipe {
+        send,
+        recv,
+        rejected,
+    })
+}
+
+async fn tcp_pipe(addr: impl ToSocketAddrs) -> eyre::Result<Pipe> {
+    let stream = TcpStream::connect(addr).await?;
+    stream.set_nodelay(true)?;
+    let (mut read, mut write) = stream.into_split();
+    let (send, outgoing) = kanal::unbounded_async();
+    let (incoming, recv) = kanal::unbounded_async();
+    let rejected = Arc::new(OnceLock::new());
+    let reason = rejected.clone();
+    let pong = send.clone();
+    tokio::spawn(async move {
+        while let Ok(outgoing) = outgoing.recv().await {
+            let frame = match outgoing {
+                Outgoing::Message(bytes) => StreamFrame::Message(bytes),
+                Outgoing::Pong => StreamFrame::Pong,
+            };
+            if write.write_all(&frame.encode()).await.is_err() {
+                break;
+            }
+        }
+    });
+    tokio::spawn(async move {
+        loop {
+            let mut header = [0; STREAM_HEADER_LEN];
+            if read.read_exact(&mut header).await.is_err() {
+                break;
+            }
+            let Ok((tag, len)) = StreamFrame::header(header) else {
+                break;
+            };
+            let mut body = vec![0; len];
+            if read.read_exact(&mut body).await.is_err() {
+                break;
+            }
+            match StreamFrame::decode(tag, body) {
+                Ok(StreamFrame::Message(bytes)) => {
+                    if incoming.send(bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(StreamFrame::Ping) => {
+                    let _ = pong.send(Outgoing::Pong).await;
+                }
+                Ok(StreamFrame::Pong) => {}
+                Ok(StreamFrame::Close { code, reason: why }) => {
+                    if code == BAN_CLOSE_CODE {
+                        let _ = reason.set(why);
+                    }
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    Ok(Pipe {
+        send,
+        recv,
+        rejected,
+    })
+}
+
+/// A connection to the server from a native program, such as a bot, load test or
+/// native frontend. Calls mirror [`Msg`], while state arrives through [`Updates`].
+pub struct Client<Q: AsQueue> {
+    token: ClientToken,
+    send: kanal::AsyncSender<Outgoing>,
+    next_id: Arc<AtomicU64>,
+    _phantom: PhantomData<Q>,
+}
+
+impl<Q: AsQueue> Clone for Client<Q> {
+    fn clone(&self) -> Self {
+        Self {
+            token: self.token,
+            send: self.send.clone(),
+            next_id: self.next_id.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<Q: AsQueue + Serialize + DeserializeOwned> Client<Q> {
+    /// Connect to the server's websocket endpoint, e.g. `ws://localhost:3000/ws`
+    pub async fn connect(url: &str, token: ClientToken) -> eyre::Result<(Self, Updates<Q>)> {
+        Self::handshake(ws_pipe(url).await?, token).await
+    }
+    /// Connect to a server accepting raw TCP clients
+    pub async fn connect_tcp(
+        addr: impl ToSocketAddrs,
+        token: ClientToken,
+    ) -> eyre::Result<(Self, Updates<Q>)> {
+        Self::handshake(tcp_pipe(addr).await?, token).await
+    }
+    async fn handshake(pipe: Pipe, token: ClientToken) -> eyre::Result<(Self, Updates<Q>)> {
+        let Pipe {
+            send,
+            recv,
+            rejected,
+        } = pipe;
+        let hello = protocol::encode(&Hello::new::<Q>())?;
+        send.send(Outgoing::Message(hello)).await?;
+        loop {
+            let Ok(bytes) = recv.recv().await else {
+                return Err(match rejected.get() {
+                    Some(reason) => Rejected(reason.clone()).into(),
+                    None => eyre::eyre!("connection closed during handshake"),
+                });
+            };
+            let envelope = Envelope::parse(&bytes)?;
+            match envelope.kind {
+                Kind::Welcome => break,
+                Kind::Reject => return Err(Rejected(envelope.decode::<Reject>()?.0).into()),
+                _ => {}
+            }
+        }
+        let client = Self {
+            token,
+            send,
+            next_id: Arc::new(AtomicU64::new(1)),
+            _phantom: PhantomData,
+        };
+        Ok((client, Updates::spawn(recv, rejected)))
+    }
+    pub async fn send(&self, msg: Msg<Q>) -> eyre::Result<()> {
+        let bytes = protocol::encode(&msg)?;
+        self.send.send(Outgoing::Message(bytes)).await?;
+        Ok(())
+    }
+    pub fn is_closed(&self) -> bool {
+        self.send.is_closed() || self.send.is_disconnected()
+    }
+    pub async fn join(&self, queue: Q) -> eyre::Result<()> {
+        self.send(Msg::join(self.token, queue)).await
+    }
+    pub async fn reconnect(&self, queue: Q) -> eyre::Result<()> {
+        self.send(Msg::reconnect(self.token, queue)).await
+    }
+    pub async fn accept(&self, queue: Q) -> eyre::Result<()> {
+        self.send(Msg::accept(self.token, queue)).await
+    }
+    /// Accept and contribute `entropy` to the seed of a provably fair session
+    pub async fn accept_with(&self, queue: Q, entropy: Entropy) -> eyre::Result<()> {
+        self.send(Msg::accept_with(self.token, queue, entropy))
+            .await
+    }
+    pub async fn vote(&self, queue: Q, settings: SettingsOf<Q::Action>) -> eyre::Result<()> {
+        self.send(Msg::vote(self.token, queue, settings)).await
+    }
+    pub async fn leave(&self, queue: Q) -> eyre::Result<()> {
+        self.send(Msg::leave(self.token, queue)).await
+    }
+    pub async fn create(&self, queue: Q) -> eyre::Result<()> {
+        self.send(Msg::create(self.token, queue)).await
+    }
+    pub async fn join_code(&self, queue: Q, code: InviteCode) -> eyre::Result<()> {
+        self.send(Msg::join_code(self.token, queue, code)).await
+    }
+    pub async fn start(&self, queue: Q) -> eyre::Result<()> {
+        self.send(Msg::start(self.token, queue)).await
+    }
+    pub async fn spectate(&self, queue: Q, session: u64) -> eyre::Result<()> {
+        self.send(Msg::spectate(self.token, queue, session)).await
+    }
+    /// Send an action, returning the id its [`ActionReply`] will carry
+    pub async fn action(&self, queue: Q, action: Q::Action) -> eyre::Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.send(Msg::action(self.token, queue, action, id))
+            .await?;
+        Ok(id)
+    }
+}
+
+/// Replies and event batches kept until read, beyond which new ones are dropped
+const REPLY_LIMIT: usize = 64;
+
+/// State from the server with session deltas already applied, so every item is
+/// the client's complete view, along with replies to this client's actions and
+/// transient session events.
+pub struct Updates<Q: AsQueue> {
+    states: kanal::AsyncReceiver<StateInfo<Q::Action>>,
+    replies: kanal::AsyncReceiver<ActionReply<Q::Action>>,
+    events: kanal::AsyncReceiver<Vec<<Q::Action as Action>::Event>>,
+    rejected: Arc<OnceLock<String>>,
+}
+
+impl<Q: AsQueue> Updates<Q> {
+    fn spawn(recv: kanal::AsyncReceiver<Vec<u8>>, rejected: Arc<OnceLock<String>>) -> Self {
+        let (send, states) = kanal::unbounded_async();
+        let (reply, replies) = kanal::bounded_async(REPLY_LIMIT);
+        let (event, events) = kanal::bounded_async(REPLY_LIMIT);
+        tokio::spawn(async move {
+            let mut state = StateInfo::<Q::Action>::Closed;
+            while let Ok(bytes) = recv.recv().await {
+                let Ok(envelope) = Envelope::parse(&bytes) else {
+                    continue;
+                };
+                match envelope.kind {
+                    Kind::State => {
+                        let Ok(update) = envelope.decode::<StateInfo<Q::Action>>() else {
+                            continue;
+                        };
+                        if state.update(update) && send.send(state.clone()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Kind::Reply => {
+                        if let Ok(decoded) = envelope.decode::<ActionReply<Q::Action>>() {
+                            let _ = reply.try_send(decoded);
+                        }
+                    }
+                    Kind::Events => {
+                        if let Ok(Events(decoded)) = envelope.decode::<Events<Q::Action>>() {
+                            let _ = event.try_send(decoded);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+        Self {
+            states,
+            replies,
+            events,
+            rejected,
+        }
+    }
+    /// Why the server ended the connection, if it refused it rather than dropping it,
+    /// e.g. for a ban. Set by the time [`Updates::recv`] returns `None`.
+    pub fn rejected(&self) -> Option<Rejected> {
+        self.rejected.get().cloned().map(Rejected)
+    }
+    /// Next state, or `None` once the connection is closed
+    pub async fn recv(&self) -> Option<StateInfo<Q::Action>> {
+        self.states.recv().await.ok()
+    }
+    pub fn stream(&self) -> impl futures_util::Stream<Item = StateInfo<Q::Action>> + '_ {
+        self.states.stream()
+    }
+    /// Next reply to an action, or `None` once the connection is closed
+    pub async fn reply(&self) -> Option<ActionReply<Q::Action>> {
+        self.replies.recv().await.ok()
+    }
+    /// Next batch of session events, or `None` once the connection is closed
+    pub async fn events(&self) -> Option<Vec<<Q::Action as Action>::Event>> {
+        self.events.recv().await.ok()
+    }
+}
@@ -0,0 +1,619 @@
+use crate::backoff::Backoff;
+use bevy::{ecs::system::SystemParam, prelude::*};
+use core::{marker::PhantomData, time::Duration};
+use serde::{Serialize, de::DeserializeOwned};
+use session::{
+    action::Action,
+    fair::Entropy,
+    info::{Info, StateInfo},
+    invite::InviteCode,
+    msg::{Msg, MsgType},
+    protocol::ActionReply,
+    queue::AsQueue,
+    settings::SettingsOf,
+    token::ClientToken,
+};
+
+pub type SessionInfoOf<Q> =
+    Info<<<Q as AsQueue>::Action as Action>::User, <<Q as AsQueue>::Action as Action>::Shared>;
+
+/// Connects a bevy frontend to the server at `url`, for the queue enum `Q`
+pub struct IliumPlugin<Q: AsQueue> {
+    url: String,
+    token: ClientToken,
+    backoff: Backoff,
+    _phantom: PhantomData<Q>,
+}
+
+impl<Q: AsQueue> IliumPlugin<Q> {
+    pub fn new(url: impl Into<String>, token: ClientToken) -> Self {
+        Self {
+            url: url.into(),
+            token,
+            backoff: Backoff::default(),
+            _phantom: PhantomData,
+        }
+    }
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+}
+
+/// Which part of the matchmaking flow the client is in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Closed,
+    Queue,
+    Private,
+    Lobby,
+    Session,
+    /// The session is over, its final state still in [`SessionInfo`]
+    Ended,
+}
+
+impl Phase {
+    pub fn of<A: Action>(state: &StateInfo<A>) -> Self {
+        match state {
+            StateInfo::Closed => Self::Closed,
+            StateInfo::Queue => Self::Queue,
+            StateInfo::Private { .. } => Self::Private,
+            StateInfo::Lobby(_) => Self::Lobby,
+            StateInfo::Session(_) | StateInfo::SessionDelta(_) => Self::Session,
+            StateInfo::Ended { .. } => Self::Ended,
+        }
+    }
+}
+
+/// Sent when the server resolves or rejects an action sent with [`Ilium::act`]
+#[derive(Clone, Debug, bevy::prelude::Message)]
+pub struct ActionReplied<Q: AsQueue>(pub ActionReply<Q::Action>);
+
+/// A transient event emitted by the session, in the order it was emitted
+#[derive(Clone, Debug, bevy::prelude::Message)]
+pub struct SessionEvent<Q: AsQueue>(pub <Q::Action as Action>::Event);
+
+/// Sent whenever the client moves between queue, lobby and session
+#[derive(Clone, Copy, Debug, bevy::prelude::Message)]
+pub struct PhaseChanged {
+    pub from: Phase,
+    pub to: Phase,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Resource)]
+pub enum Status {
+    #[default]
+    Connecting,
+    Open,
+    /// Waiting before reconnect attempt `attempt`, counting from 1
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+    },
+    /// The connection was refused, e.g. for a schema mismatch or a ban, and is not
+    /// retried. Holds the server's reason.
+    Rejected(String),
+}
+
+/// Latest state from the server, with session deltas applied
+#[derive(Resource)]
+pub struct ServerState<Q: AsQueue>(pub StateInfo<Q::Action>);
+
+impl<Q: AsQueue> Default for ServerState<Q> {
+    fn default() -> Self {
+        Self(StateInfo::Closed)
+    }
+}
+
+/// Latest session info, `None` outside of a session
+#[derive(Resource)]
+pub struct SessionInfo<Q: AsQueue>(pub Option<SessionInfoOf<Q>>);
+
+impl<Q: AsQueue> Default for SessionInfo<Q> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+enum LinkEvent<Q: AsQueue> {
+    Status(Status),
+    State(StateInfo<Q::Action>),
+    Reply(ActionReply<Q::Action>),
+    Events(Vec<<Q::Action as Action>::Event>),
+}
+
+#[derive(Resource)]
+struct Incoming<Q: AsQueue>(kanal::Receiver<LinkEvent<Q>>);
+
+/// Outgoing half of the connection, see [`Ilium`]
+#[derive(Resource)]
+pub struct Connection<Q: AsQueue> {
+    token: ClientToken,
+    queue: Option<Q>,
+    next_id: u64,
+    send: kanal::Sender<Msg<Q>>,
+}
+
+/// System parameter for sending messages to the server. Messages sent while
+/// disconnected are delivered once the connection is back.
+#[derive(SystemParam)]
+pub struct Ilium<'w, Q: AsQueue + Clone> {
+    connection: ResMut<'w, Connection<Q>>,
+}
+
+impl<Q: AsQueue + Clone> Ilium<'_, Q> {
+    pub fn send(&mut self, msg: Msg<Q>) {
+        self.connection.queue = match msg.msg_type {
+            MsgType::Leave => None,
+            _ => Some(msg.queue.clone()),
+        };
+        let _ = self.connection.send.send(msg);
+    }
+    /// Queue most recently joined, which [`Ilium::act`] sends actions for
+    pub fn queue(&self) -> Option<&Q> {
+        self.connection.queue.as_ref()
+    }
+    pub fn join(&mut self, queue: Q) {
+        self.send(Msg::join(self.connection.token, queue));
+    }
+    pub fn accept(&mut self, queue: Q) {
+        self.send(Msg::accept(self.connection.token, queue));
+    }
+    /// Accept and contribute `entropy` to the seed of a provably fair session
+    pub fn accept_with(&mut self, queue: Q, entropy: Entropy) {
+        self.send(Msg::accept_with(self.connection.token, queue, entropy));
+    }
+    pub fn vote(&mut self, queue: Q, settings: SettingsOf<Q::Action>) {
+        self.send(Msg::vote(self.connection.token, queue, settings));
+    }
+    pub fn leave(&mut self, queue: Q) {
+        self.send(Msg::leave(self.connection.token, queue));
+    }
+    pub fn create(&mut self, queue: Q) {
+        self.send(Msg::create(self.connection.token, queue));
+    }
+    pub fn join_code(&mut self, queue: Q, code: InviteCode) {
+        self.send(Msg::join_code(self.connection.token, queue, code));
+    }
+    pub fn start(&mut self, queue: Q) {
+        self.send(Msg::start(self.connection.token, queue));
+    }
+    pub fn spectate(&mut self, queue: Q, session: u64) {
+        self.send(Msg::spectate(self.connection.token, queue, session));
+    }
+    /// Send an action in the current queue, returning the id its [`ActionReplied`]
+    /// will carry, or `None` if no queue was joined
+    pub fn act(&mut self, action: Q::Action) -> Option<u64> {
+        let queue = self.connection.queue.clone()?;
+        let id = self.connection.next_id;
+        self.connection.next_id += 1;
+        self.send(Msg::action(self.connection.token, queue, action, id));
+        Some(id)
+    }
+}
+
+fn receive<Q: AsQueue>(
+    incoming: Res<Incoming<Q>>,
+    mut status: ResMut<Status>,
+    mut state: ResMut<ServerState<Q>>,
+    mut session: ResMut<SessionInfo<Q>>,
+    mut phases: MessageWriter<PhaseChanged>,
+    mut replies: MessageWriter<ActionReplied<Q>>,
+    mut events: MessageWriter<SessionEvent<Q>>,
+) {
+    while let Ok(Some(event)) = incoming.0.try_recv() {
+        let from = Phase::of(&state.0);
+        match event {
+            LinkEvent::Status(new) => {
+                if new != Status::Open {
+                    state.0 = StateInfo::Closed;
+                }
+                *status = new;
+            }
+            LinkEvent::State(update) => {
+                state.0.update(update);
+            }
+            LinkEvent::Reply(reply) => {
+                replies.write(ActionReplied(reply));
+                continue;
+            }
+            LinkEvent::Events(batch) => {
+                events.write_batch(batch.into_iter().map(SessionEvent));
+                continue;
+            }
+        }
+        let to = Phase::of(&state.0);
+        session.0 = match &state.0 {
+            StateInfo::Session(info) | StateInfo::Ended { info, .. } => Some(info.clone()),
+            _ => None,
+        };
+        if from != to {
+            phases.write(PhaseChanged { from, to });
+        }
+    }
+}
+
+impl<Q> Plugin for IliumPlugin<Q>
+where
+    Q: AsQueue + Clone + Serialize + DeserializeOwned,
+{
+    fn build(&self, app: &mut App) {
+        let (send, commands) = kanal::unbounded();
+        let (events, incoming) = kanal::unbounded();
+        link::spawn::<Q>(
+            app,
+            self.url.clone(),
+            self.token,
+            self.backoff,
+            commands,
+            events,
+        );
+        app.insert_resource(Connection::<Q> {
+            token: self.token,
+            queue: None,
+            next_id: 1,
+            send,
+        })
+        .insert_resource(Incoming::<Q>(incoming))
+        .init_resource::<Status>()
+        .init_resource::<ServerState<Q>>()
+        .init_resource::<SessionInfo<Q>>()
+        .add_message::<PhaseChanged>()
+        .add_message::<ActionReplied<Q>>()
+        .add_message::<SessionEvent<Q>>()
+        .add_systems(PreUpdate, receive::<Q>);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod link {
+    use super::*;
+    use crate::native::{Client, Rejected};
+    use std::hash::{BuildHasher, RandomState};
+
+    fn random() -> f64 {
+        RandomState::new().hash_one(std::time::SystemTime::now()) as f64 / u64::MAX as f64
+    }
+
+    /// Run the connection on its own thread, reconnecting whenever it drops
+    pub(super) fn spawn<Q>(
+        _app: &mut App,
+        url: String,
+        token: ClientToken,
+        backoff: Backoff,
+        commands: kanal::Receiver<Msg<Q>>,
+        events: kanal::Sender<LinkEvent<Q>>,
+    ) where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            runtime.block_on(run(url, token, backoff, commands.to_async(), events))
+        });
+    }
+
+    async fn run<Q>(
+        url: String,
+        token: ClientToken,
+        backoff: Backoff,
+        commands: kanal::AsyncReceiver<Msg<Q>>,
+        events: kanal::Sender<LinkEvent<Q>>,
+    ) -> eyre::Result<()>
+    where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        let mut attempts = 0;
+        let mut connected = false;
+        let mut queue: Option<Q> = None;
+        // a message taken off the channel whose send failed, retried once reconnected
+        let mut unsent: Option<Msg<Q>> = None;
+        loop {
+            match Client::<Q>::connect(&url, token).await {
+                Ok((client, updates)) => {
+                    attempts = 0;
+                    events.send(LinkEvent::Status(Status::Open))?;
+                    let mut open = match &queue {
+                        Some(queue) if connected => client.reconnect(queue.clone()).await.is_ok(),
+                        _ => true,
+                    };
+                    connected = true;
+                    if open && let Some(msg) = unsent.take() {
+                        open = client.send(msg.clone()).await.is_ok();
+                        if !open {
+                            unsent = Some(msg);
+                        }
+                    }
+                    if open {
+                        loop {
+                            tokio::select! {
+                                update = updates.recv() => match update {
+                                    Some(update) => events.send(LinkEvent::State(update))?,
+                                    None => match updates.rejected() {
+                                        Some(rejected) => {
+                                            events.send(LinkEvent::Status(Status::Rejected(rejected.0.clone())))?;
+                                            return Err(rejected.into());
+                                        }
+                                        None => break,
+                                    },
+                                },
+                                Some(reply) = updates.reply() => events.send(LinkEvent::Reply(reply))?,
+                                Some(batch) = updates.events() => events.send(LinkEvent::Events(batch))?,
+                                msg = commands.recv() => {
+                                    let msg = msg?;
+                                    queue = match msg.msg_type {
+                                        MsgType::Leave => None,
+                                        _ => Some(msg.queue.clone()),
+                                    };
+                                    if client.send(msg.clone()).await.is_err() {
+                                        unsent = Some(msg);
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => {
+                    if let Some(Rejected(reason)) = e.downcast_ref::<Rejected>() {
+                        events.send(LinkEvent::Status(Status::Rejected(reason.clone())))?;
+                        return Err(e);
+                    }
+                }
+            }
+            let delay = backoff.delay(attempts, random());
+            attempts = attempts.saturating_add(1);
+            events.send(LinkEvent::Status(Status::Reconnecting {
+                attempt: attempts,
+                delay,
+            }))?;
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod link {
+    use super::*;
+    use session::protocol::{self, BAN_CLOSE_CODE, Envelope, Events, Hello, Kind, Reject};
+    use std::{cell::RefCell, rc::Rc};
+    use wasm_bindgen::prelude::*;
+    use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+    struct Socket {
+        ws: WebSocket,
+        _onopen: Closure<dyn FnMut(Event)>,
+        _onmessage: Closure<dyn FnMut(MessageEvent)>,
+        _onclose: Closure<dyn FnMut(CloseEvent)>,
+    }
+
+    impl Drop for Socket {
+        fn drop(&mut self) {
+            self.ws.set_onopen(None);
+            self.ws.set_onmessage(None);
+            self.ws.set_onerror(None);
+            self.ws.set_onclose(None);
+            let _ = self.ws.close();
+        }
+    }
+
+    struct Link<Q: AsQueue> {
+        url: String,
+        token: ClientToken,
+        backoff: Backoff,
+        socket: Option<Socket>,
+        open: bool,
+        connected: bool,
+        stopped: bool,
+        retrying: bool,
+        attempts: u32,
+        queue: Option<Q>,
+        events: kanal::Sender<LinkEvent<Q>>,
+        _timer: Option<Closure<dyn FnMut()>>,
+    }
+
+    type Shared<Q> = Rc<RefCell<Link<Q>>>;
+
+    /// Browser sockets are not `Send`, so the link lives in a non-send resource
+    /// and outgoing messages are written by a system
+    struct Commands<Q: AsQueue>(kanal::Receiver<Msg<Q>>, Shared<Q>);
+
+    pub(super) fn spawn<Q>(
+        app: &mut App,
+        url: String,
+        token: ClientToken,
+        backoff: Backoff,
+        commands: kanal::Receiver<Msg<Q>>,
+        events: kanal::Sender<LinkEvent<Q>>,
+    ) where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        let link = Rc::new(RefCell::new(Link {
+            url,
+            token,
+            backoff,
+            socket: None,
+            open: false,
+            connected: false,
+            stopped: false,
+            retrying: false,
+            attempts: 0,
+            queue: None,
+            events,
+            _timer: None,
+        }));
+        connect(&link);
+        app.insert_non_send_resource(Commands(commands, link))
+            .add_systems(PostUpdate, flush::<Q>);
+    }
+
+    fn send_raw<Q: AsQueue>(link: &Link<Q>, bytes: &[u8]) {
+        if let Some(socket) = &link.socket {
+            let _ = socket.ws.send_with_u8_array(bytes);
+        }
+    }
+
+    fn flush<Q>(commands: NonSend<Commands<Q>>)
+    where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        let Commands(receiver, link) = &*commands;
+        let mut link = link.borrow_mut();
+        // messages wait in the channel until the handshake completes
+        while link.open
+            && let Ok(Some(msg)) = receiver.try_recv()
+        {
+            link.queue = match msg.msg_type {
+                MsgType::Leave => None,
+                _ => Some(msg.queue.clone()),
+            };
+            if let Ok(bytes) = protocol::encode(&msg) {
+                send_raw(&link, &bytes);
+            }
+        }
+    }
+
+    fn connect<Q>(shared: &Shared<Q>)
+    where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        let url = shared.borrow().url.clone();
+        let Ok(ws) = WebSocket::new(&url) else {
+            // an invalid url will not become valid by retrying
+            let mut link = shared.borrow_mut();
+            link.stopped = true;
+            let _ = link.events.send(LinkEvent::Status(Status::Rejected(format!(
+                "invalid url {url}"
+            ))));
+            return;
+        };
+        ws.set_binary_type(BinaryType::Arraybuffer);
+        let onopen = {
+            let shared = shared.clone();
+            Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                if let Ok(hello) = protocol::encode(&Hello::new::<Q>()) {
+                    send_raw(&shared.borrow(), &hello);
+                }
+            })
+        };
+        let onmessage = {
+            let shared = shared.clone();
+            Closure::<dyn FnMut(MessageEvent)>::new(move |msg: MessageEvent| {
+                on_message(&shared, msg)
+            })
+        };
+        let onclose = {
+            let shared = shared.clone();
+            Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| {
+                let mut link = shared.borrow_mut();
+                link.open = false;
+                // errors share this callback, and carry no code
+                if event.code() == BAN_CLOSE_CODE && !link.stopped {
+                    link.stopped = true;
+                    let _ = link
+                        .events
+                        .send(LinkEvent::Status(Status::Rejected(event.reason())));
+                }
+                drop(link);
+                retry(&shared);
+            })
+        };
+        ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(onclose.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+        let mut link = shared.borrow_mut();
+        link.retrying = false;
+        // replacing the previous socket drops its callbacks
+        link.socket = Some(Socket {
+            ws,
+            _onopen: onopen,
+            _onmessage: onmessage,
+            _onclose: onclose,
+        });
+    }
+
+    fn on_message<Q>(shared: &Shared<Q>, msg: MessageEvent)
+    where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        let Ok(buf) = msg.data().dyn_into::<js_sys::ArrayBuffer>() else {
+            return;
+        };
+        let bytes: Vec<u8> = js_sys::Uint8Array::new(&buf).to_vec();
+        let Ok(envelope) = Envelope::parse(&bytes) else {
+            return;
+        };
+        let mut link = shared.borrow_mut();
+        match envelope.kind {
+            Kind::Welcome => {
+                link.open = true;
+                link.attempts = 0;
+                let _ = link.events.send(LinkEvent::Status(Status::Open));
+                if link.connected
+                    && let Some(queue) = link.queue.clone()
+                    && let Ok(bytes) = protocol::encode(&Msg::reconnect(link.token, queue))
+                {
+                    send_raw(&link, &bytes);
+                }
+                link.connected = true;
+            }
+            Kind::Reject => {
+                let reason = envelope
+                    .decode::<Reject>()
+                    .map(|Reject(reason)| reason)
+                    .unwrap_or_default();
+                link.stopped = true;
+                let _ = link
+                    .events
+                    .send(LinkEvent::Status(Status::Rejected(reason)));
+            }
+            Kind::State => {
+                if let Ok(update) = envelope.decode::<StateInfo<Q::Action>>() {
+                    let _ = link.events.send(LinkEvent::State(update));
+                }
+            }
+            Kind::Reply => {
+                if let Ok(reply) = envelope.decode::<ActionReply<Q::Action>>() {
+                    let _ = link.events.send(LinkEvent::Reply(reply));
+                }
+            }
+            Kind::Events => {
+                if let Ok(Events(batch)) = envelope.decode::<Events<Q::Action>>() {
+                    let _ = link.events.send(LinkEvent::Events(batch));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn retry<Q>(shared: &Shared<Q>)
+    where
+        Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    {
+        let mut link = shared.borrow_mut();
+        // errors are followed by a close, so only the first schedules a reconnect
+        if link.stopped || link.retrying {
+            return;
+        }
+        link.retrying = true;
+        let delay = link.backoff.delay(link.attempts, js_sys::Math::random());
+        link.attempts = link.attempts.saturating_add(1);
+        let _ = link.events.send(LinkEvent::Status(Status::Reconnecting {
+            attempt: link.attempts,
+            delay,
+        }));
+        let timer = {
+            let shared = shared.clone();
+            Closure::<dyn FnMut()>::new(move || connect(&shared))
+        };
+        if let Some(window) = web_sys::window() {
+            let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+                timer.as_ref().unchecked_ref(),
+                delay.as_millis() as i32,
+            );
+        }
+        link._timer = Some(timer);
+    }
+}
@@ -0,0 +1,571 @@
+use crate::backoff::Backoff;
+use codee::Encoder;
+use leptos::{leptos_dom::helpers::TimeoutHandle, logging, prelude::*};
+use serde::{Serialize, de::DeserializeOwned};
+use session::{
+    action::Action,
+    codec::ProtocolCodec,
+    fair::Entropy,
+    info::{AsInfo, StateInfo},
+    invite::InviteCode,
+    msg::{Msg, MsgType},
+    predict::{Predict, Prediction},
+    protocol::{self, ActionReply, BAN_CLOSE_CODE, Envelope, Events, Hello, Kind, Reject},
+    queue::AsQueue,
+    settings::SettingsOf,
+    token::ClientToken,
+};
+use std::collections::VecDeque;
+use wasm_bindgen::prelude::*;
+use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+/// Messages kept while disconnected, beyond which the oldest is rejected
+const BUFFER_LIMIT: usize = 64;
+/// Age in milliseconds after which a buffered message is rejected instead of sent
+const BUFFER_TIMEOUT: f64 = 10_000.0;
+
+#[derive(Debug, PartialEq, Eq, Clone, Hash, Copy)]
+pub enum Ready {
+    Connecting,
+    Open,
+    /// Waiting `delay_ms` before reconnect attempt `attempt`, counting from 1
+    Reconnecting {
+        attempt: u32,
+        delay_ms: u64,
+    },
+    Closed,
+}
+
+/// A websocket and the callbacks attached to it. Dropping it detaches and frees the
+/// callbacks and closes the socket, so it must not be dropped from inside one of them.
+struct Connection {
+    ws: WebSocket,
+    _onopen: Closure<dyn FnMut(Event)>,
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onclose: Closure<dyn FnMut(CloseEvent)>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.ws.set_onopen(None);
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onclose(None);
+        let _ = self.ws.close();
+    }
+}
+
+struct State<Q: AsQueue> {
+    url: String,
+    token: ClientToken,
+    /// Queue of the last message sent, used to rejoin after reconnecting
+    queue: Option<Q>,
+    connection: Option<Connection>,
+    timer: Option<TimeoutHandle>,
+    backoff: Backoff,
+    attempts: u32,
+    /// Messages sent while disconnected, with the time they were sent
+    buffer: VecDeque<(f64, Msg<Q>)>,
+    /// Id for the next action, increasing for the lifetime of the handle
+    next_id: u64,
+    /// Whether the next handshake follows a dropped connection
+    reconnecting: bool,
+    /// Set by [`Handle::close`] or a rejected handshake, no automatic reconnects
+    stopped: bool,
+    /// Messages were already rejected this tick, so more are added to the same batch
+    rejecting: bool,
+}
+
+/// A connection to the server exposed as reactive signals
+pub struct Handle<Q: AsQueue> {
+    ready: ReadSignal<Ready>,
+    set_ready: WriteSignal<Ready>,
+    info: ReadSignal<StateInfo<Q::Action>>,
+    set_info: WriteSignal<StateInfo<Q::Action>>,
+    pending: ReadSignal<usize>,
+    set_pending: WriteSignal<usize>,
+    rejected: ReadSignal<Vec<Msg<Q>>>,
+    set_rejected: WriteSignal<Vec<Msg<Q>>>,
+    refused: ReadSignal<Option<String>>,
+    set_refused: WriteSignal<Option<String>>,
+    reply: ReadSignal<Option<ActionReply<Q::Action>>>,
+    set_reply: WriteSignal<Option<ActionReply<Q::Action>>>,
+    events: ReadSignal<Vec<<Q::Action as Action>::Event>>,
+    set_events: WriteSignal<Vec<<Q::Action as Action>::Event>>,
+    state: StoredValue<State<Q>, LocalStorage>,
+}
+
+impl<Q: AsQueue> Clone for Handle<Q> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Q: AsQueue> Copy for Handle<Q> {}
+
+impl<Q> Handle<Q>
+where
+    Q: AsQueue + Clone + Serialize + DeserializeOwned,
+{
+    pub fn ready(&self) -> Signal<Ready> {
+        self.ready.into()
+    }
+    /// Current state, with session deltas already applied
+    pub fn info(&self) -> Signal<StateInfo<Q::Action>> {
+        self.info.into()
+    }
+    /// Number of messages buffered until the connection is back
+    pub fn pending(&self) -> Signal<usize> {
+        self.pending.into()
+    }
+    /// Messages most recently dropped instead of sent, for being too old, exceeding the
+    /// buffer or being sent after the connection was closed or rejected. Messages dropped
+    /// in the same tick are reported together.
+    pub fn rejected(&self) -> Signal<Vec<Msg<Q>>> {
+        self.rejected.into()
+    }
+    /// Why the server refused the connection, e.g. for a schema mismatch or a ban.
+    /// A refused handle stays closed until [`Handle::open`] is called again.
+    pub fn refused(&self) -> Signal<Option<String>> {
+        self.refused.into()
+    }
+    /// Latest reply to an action sent from this handle
+    pub fn reply(&self) -> Signal<Option<ActionReply<Q::Action>>> {
+        self.reply.into()
+    }
+    /// Latest batch of transient session events
+    pub fn events(&self) -> Signal<Vec<<Q::Action as Action>::Event>> {
+        self.events.into()
+    }
+    pub fn set_backoff(&self, backoff: Backoff) {
+        self.state.update_value(|state| state.backoff = backoff);
+    }
+    pub fn open(&self) {
+        if self.ready.get_untracked() == Ready::Closed {
+            self.state.update_value(|state| {
+                state.attempts = 0;
+                state.stopped = false;
+            });
+            self.set_refused.try_set(None);
+            self.connect();
+        }
+    }
+    pub fn close(&self) {
+        self.state.try_update_value(|state| {
+            state.stopped = true;
+            if let Some(timer) = state.timer.take() {
+                timer.clear();
+            }
+            state.connection = None;
+        });
+        self.set_ready.try_set(Ready::Closed);
+    }
+    fn send_raw(&self, data: &[u8]) {
+        self.state.with_value(|state| {
+            if let Some(connection) = &state.connection {
+                let _ = connection.ws.send_with_u8_array(data);
+            }
+        });
+    }
+    /// Report `msgs` as rejected, alongside any others rejected this tick
+    fn reject(&self, msgs: Vec<Msg<Q>>) {
+        let batched = self
+            .state
+            .try_update_value(|state| core::mem::replace(&mut state.rejecting, true))
+            .unwrap_or_default();
+        if batched {
+            self.set_rejected
+                .try_update(|rejected| rejected.extend(msgs));
+            return;
+        }
+        self.set_rejected.try_set(msgs);
+        let state = self.state;
+        leptos::leptos_dom::helpers::queue_microtask(move || {
+            state.try_update_value(|state| state.rejecting = false);
+        });
+    }
+    /// Send `msg`, or buffer it until the connection is back. Rejected right away once
+    /// the connection was closed or the server refused it.
+    pub fn send(&self, msg: Msg<Q>) {
+        if self.state.with_value(|state| state.stopped) {
+            self.reject(vec![msg]);
+            return;
+        }
+        self.state.update_value(|state| {
+            state.queue = match msg.msg_type {
+                MsgType::Leave => None,
+                _ => Some(msg.queue.clone()),
+            }
+        });
+        if self.ready.get_untracked() == Ready::Open {
+            if let Ok(data) = ProtocolCodec::encode(&msg) {
+                self.send_raw(&data);
+            }
+            return;
+        }
+        let overflow = self
+            .state
+            .with_value(|state| state.buffer.len() >= BUFFER_LIMIT);
+        let dropped = self
+            .state
+            .try_update_value(|state| {
+                state.buffer.push_back((js_sys::Date::now(), msg));
+                overflow.then(|| state.buffer.pop_front()).flatten()
+            })
+            .flatten();
+        if let Some((_, msg)) = dropped {
+            self.reject(vec![msg]);
+        }
+        self.set_pending
+            .try_set(self.state.with_value(|state| state.buffer.len()));
+        self.open();
+    }
+    /// Send buffered messages after a reconnect, rejecting those that waited too long
+    fn flush(&self) {
+        let buffer = self
+            .state
+            .try_update_value(|state| core::mem::take(&mut state.buffer))
+            .unwrap_or_default();
+        self.set_pending.try_set(0);
+        let now = js_sys::Date::now();
+        let (send, reject): (Vec<_>, Vec<_>) = buffer
+            .into_iter()
+            .partition(|(sent, _)| now - sent <= BUFFER_TIMEOUT);
+        for (_, msg) in send {
+            if let Ok(data) = ProtocolCodec::encode(&msg) {
+                self.send_raw(&data);
+            }
+        }
+        if !reject.is_empty() {
+            self.reject(reject.into_iter().map(|(_, msg)| msg).collect());
+        }
+    }
+    /// Queue most recently joined, cleared by leaving it
+    pub fn queue(&self) -> Option<Q> {
+        self.state.with_value(|state| state.queue.clone())
+    }
+    fn token(&self) -> ClientToken {
+        self.state.with_value(|state| state.token)
+    }
+    pub fn join(&self, queue: Q) {
+        self.send(Msg::join(self.token(), queue));
+    }
+    pub fn accept(&self, queue: Q) {
+        self.send(Msg::accept(self.token(), queue));
+    }
+    /// Accept and contribute `entropy` to the seed of a provably fair session
+    pub fn accept_with(&self, queue: Q, entropy: Entropy) {
+        self.send(Msg::accept_with(self.token(), queue, entropy));
+    }
+    pub fn vote(&self, queue: Q, settings: SettingsOf<Q::Action>) {
+        self.send(Msg::vote(self.token(), queue, settings));
+    }
+    pub fn leave(&self, queue: Q) {
+        self.send(Msg::leave(self.token(), queue));
+    }
+    pub fn create(&self, queue: Q) {
+        self.send(Msg::create(self.token(), queue));
+    }
+    pub fn join_code(&self, queue: Q, code: InviteCode) {
+        self.send(Msg::join_code(self.token(), queue, code));
+    }
+    pub fn start(&self, queue: Q) {
+        self.send(Msg::start(self.token(), queue));
+    }
+    pub fn spectate(&self, queue: Q, session: u64) {
+        self.send(Msg::spectate(self.token(), queue, session));
+    }
+    /// Send an action, returning the id its reply will carry
+    pub fn action(&self, queue: Q, action: Q::Action) -> u64 {
+        let id = self
+            .state
+            .try_update_value(|state| {
+                let id = state.next_id;
+                state.next_id += 1;
+                id
+            })
+            .unwrap_or_default();
+        self.send(Msg::action(self.token(), queue, action, id));
+        id
+    }
+    fn connect(&self) {
+        let url = self.state.with_value(|state| state.url.clone());
+        let ws = WebSocket::new(&url).unwrap_throw();
+        ws.set_binary_type(BinaryType::Arraybuffer);
+        self.set_ready.set(Ready::Connecting);
+        let handle = *self;
+        let onopen = Closure::<dyn FnMut(Event)>::new(move |_: Event| handle.on_open());
+        let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(move |msg: MessageEvent| {
+            handle.on_message(msg)
+        });
+        let onclose =
+            Closure::<dyn FnMut(CloseEvent)>::new(move |event: CloseEvent| handle.on_close(event));
+        ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(onclose.as_ref().unchecked_ref()));
+        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+        let connection = Connection {
+            ws,
+            _onopen: onopen,
+            _onmessage: onmessage,
+            _onclose: onclose,
+        };
+        // replacing the previous connection drops its callbacks
+        self.state.update_value(|state| {
+            state.timer = None;
+            state.connection = Some(connection);
+        });
+    }
+    fn on_open(&self) {
+        if let Ok(hello) = protocol::encode(&Hello::new::<Q>()) {
+            self.send_raw(&hello);
+        }
+    }
+    fn on_message(&self, msg: MessageEvent) {
+        let Ok(buf) = msg.data().dyn_into::<js_sys::ArrayBuffer>() else {
+            return;
+        };
+        let bytes: Vec<u8> = js_sys::Uint8Array::new(&buf).to_vec();
+        let Ok(envelope) = Envelope::parse(&bytes) else {
+            return;
+        };
+        match envelope.kind {
+            Kind::Welcome => {
+                self.set_ready.try_set(Ready::Open);
+                let rejoin = self
+                    .state
+                    .try_update_value(|state| {
+                        state.attempts = 0;
+                        let reconnecting = core::mem::take(&mut state.reconnecting);
+                        reconnecting
+                            .then(|| state.queue.clone())
+                            .flatten()
+                            .map(|queue| Msg::reconnect(state.token, queue))
+                    })
+                    .flatten();
+                if let Some(msg) = rejoin
+                    && let Ok(data) = ProtocolCodec::encode(&msg)
+                {
+                    self.send_raw(&data);
+                }
+                self.flush();
+            }
+            Kind::Reject => {
+                let reason = envelope
+                    .decode::<Reject>()
+                    .map(|Reject(reason)| reason)
+                    .unwrap_or_default();
+                self.refuse(reason);
+            }
+            Kind::State => {
+                if let Ok(update) = envelope.decode::<StateInfo<Q::Action>>() {
+                    self.set_info.try_update(|info| {
+                        info.update(update);
+                    });
+                }
+            }
+            Kind::Reply => {
+                if let Ok(reply) = envelope.decode::<ActionReply<Q::Action>>() {
+                    self.set_reply.try_set(Some(reply));
+                }
+            }
+            Kind::Events => {
+                if let Ok(Events(events)) = envelope.decode::<Events<Q::Action>>() {
+                    self.set_events.try_set(events);
+                }
+            }
+            _ => {}
+        }
+    }
+    /// Stop reconnecting, as retrying a refused connection is refused again
+    fn refuse(&self, reason: String) {
+        logging::log!("connection rejected: {reason}");
+        self.state.try_update_value(|state| state.stopped = true);
+        self.set_refused.try_set(Some(reason));
+    }
+    fn on_close(&self, event: CloseEvent) {
+        // errors share this callback, and carry no code
+        if event.code() == BAN_CLOSE_CODE {
+            self.refuse(event.reason());
+        }
+        self.set_info.try_set(StateInfo::Closed);
+        let handle = *self;
+        self.state.try_update_value(|state| {
+            state.reconnecting = true;
+            if state.stopped {
+                handle.set_ready.try_set(Ready::Closed);
+                return;
+            }
+            // errors are followed by a close, so only the first schedules a reconnect
+            if state.timer.is_some() {
+                return;
+            }
+            let delay = state.backoff.delay(state.attempts, js_sys::Math::random());
+            state.attempts = state.attempts.saturating_add(1);
+            handle.set_ready.try_set(Ready::Reconnecting {
+                attempt: state.attempts,
+                delay_ms: delay.as_millis() as u64,
+            });
+            state.timer = set_timeout_with_handle(move || handle.connect(), delay).ok();
+        });
+    }
+}
+
+/// Client-side prediction on top of a [`Handle`]. Actions sent through it are applied
+/// locally right away, then reconciled as the server acknowledges them.
+pub struct Predicted<Q: AsQueue> {
+    handle: Handle<Q>,
+    prediction: StoredValue<Prediction<Q::Action>, LocalStorage>,
+    info: ReadSignal<Option<AsInfo<Q>>>,
+    set_info: WriteSignal<Option<AsInfo<Q>>>,
+}
+
+impl<Q: AsQueue> Clone for Predicted<Q> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Q: AsQueue> Copy for Predicted<Q> {}
+
+impl<Q> Predicted<Q>
+where
+    Q: AsQueue + Clone + Serialize + DeserializeOwned,
+    Q::Action: Predict,
+{
+    pub fn new(handle: Handle<Q>) -> Self {
+        let prediction = StoredValue::new_local(Prediction::default());
+        let (info, set_info) = signal(None);
+        Effect::new(move || {
+            let predicted = handle.info.with(|state| {
+                prediction
+                    .try_update_value(|prediction| {
+                        prediction.confirm(state);
+                        prediction.info().cloned()
+                    })
+                    .flatten()
+            });
+            set_info.try_set(predicted);
+        });
+        Self {
+            handle,
+            prediction,
+            info,
+            set_info,
+        }
+    }
+    /// Session info with unacknowledged actions applied, `None` outside of a session
+    pub fn info(&self) -> Signal<Option<AsInfo<Q>>> {
+        self.info.into()
+    }
+    /// Number of actions the server has not acknowledged yet
+    pub fn pending(&self) -> usize {
+        self.prediction
+            .try_with_value(|prediction| prediction.pending())
+            .unwrap_or_default()
+    }
+    /// Send an action and apply it locally, returning the id its reply will carry
+    pub fn action(&self, queue: Q, action: Q::Action) -> u64 {
+        let id = self.handle.action(queue, action);
+        let predicted = self
+            .prediction
+            .try_update_value(|prediction| {
+                prediction.act(id, action);
+                prediction.info().cloned()
+            })
+            .flatten();
+        self.set_info.try_set(predicted);
+        id
+    }
+}
+
+/// Connect to the server at `url` for the lifetime of the current reactive owner
+pub fn ws_handle<Q>(url: &str, token: ClientToken) -> Handle<Q>
+where
+    Q: AsQueue + Clone + Serialize + DeserializeOwned,
+{
+    let (ready, set_ready) = signal(Ready::Closed);
+    let (info, set_info) = signal(StateInfo::Closed);
+    let (pending, set_pending) = signal(0);
+    let (rejected, set_rejected) = signal(Vec::new());
+    let (refused, set_refused) = signal(None);
+    let (reply, set_reply) = signal(None);
+    let (events, set_events) = signal(Vec::new());
+    let state = StoredValue::new_local(State {
+        url: normalize_url(url),
+        token,
+        queue: None,
+        connection: None,
+        timer: None,
+        backoff: Backoff::default(),
+        attempts: 0,
+        buffer: VecDeque::new(),
+        next_id: 1,
+        reconnecting: false,
+        stopped: false,
+        rejecting: false,
+    });
+    let handle = Handle {
+        ready,
+        set_ready,
+        info,
+        set_info,
+        pending,
+        set_pending,
+        rejected,
+        set_rejected,
+        refused,
+        set_refused,
+        reply,
+        set_reply,
+        events,
+        set_events,
+        state,
+    };
+    Effect::new(move |_| handle.open());
+    on_cleanup(move || handle.close());
+    handle
+}
+
+fn normalize_url(url: &str) -> String {
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+        url.to_string()
+    } else if url.starts_with("//") {
+        format!(
+            "{}{}",
+            window()
+                .location()
+                .protocol()
+                .expect("Protocol not found")
+                .replace("http", "ws"),
+            url,
+        )
+    } else if url.starts_with('/') {
+        format!(
+            "{}//{}{}",
+            window()
+                .location()
+                .protocol()
+                .expect("Protocol not found")
+                .replace("http", "ws"),
+            window().location().host().expect("Host not found"),
+            url
+        )
+    } else {
+        let mut path = window().location().pathname().expect("Pathname not found");
+        if !path.ends_with('/') {
+            path.push('/')
+        }
+        format!(
+            "{}//{}{}{}",
+            window()
+                .location()
+                .protocol()
+                .expect("Protocol not found")
+                .replace("http", "ws"),
+            window().location().host().expect("Host not found"),
+            path,
+            url
+        )
+    }
+}
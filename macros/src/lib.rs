@@ -2,6 +2,7 @@ use proc_macro::*;
 use proc_macro_error::*;
 
 mod queue;
+mod schema;
 mod state;
 mod util;
 
@@ -11,12 +12,26 @@ pub fn queue(item: TokenStream) -> TokenStream {
     queue::derive_queue_impl(item)
 }
 
+#[proc_macro_derive(Schema)]
+#[proc_macro_error]
+pub fn schema(item: TokenStream) -> TokenStream {
+    schema::derive_schema_impl(item)
+}
+
+/// Derives `SharedState` and a `{Name}Info` struct of its open and hidden fields.
+///
+/// Updates are sent as deltas of changed fields, so every open and hidden field type must
+/// implement `PartialEq` and `Clone`.
 #[proc_macro_derive(SharedState, attributes(ilium))]
 #[proc_macro_error]
 pub fn shared(item: TokenStream) -> TokenStream {
     state::derive_state_impl(item, true)
 }
 
+/// Derives `UserState` and a `{Name}Info` struct of its open, hidden and private fields.
+///
+/// Updates are sent as deltas of changed fields, so every open, hidden and private field
+/// type must implement `PartialEq` and `Clone`.
 #[proc_macro_derive(UserState, attributes(ilium))]
 #[proc_macro_error]
 pub fn user(item: TokenStream) -> TokenStream {
@@ -6,17 +6,27 @@ use syn::*;
 
 pub fn derive_queue_impl(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
+    let queue_fingerprint =
+        session::schema::fingerprint(crate::schema::shape(&ast.ident, &ast.data).as_bytes());
+    let vis = ast.vis;
     let queue: Ident = ast.ident;
+    let state_info_name = format_ident!("{queue}StateInfo");
+    let session_info_name = format_ident!("{queue}SessionInfo");
     let action: Path = name_value(&ast.attrs, "action")
         .unwrap_or_else(|| abort_call_site!("Could not find action attribute"));
     let mut variant_name: Vec<Ident> = Vec::new();
     let mut component: Vec<Ident> = Vec::new();
     let mut lobby_name: Vec<Ident> = Vec::new();
     let mut lobby_type: Vec<Type> = Vec::new();
+    let mut lobby_size: Vec<proc_macro2::TokenTree> = Vec::new();
     let mut queue_sender: Vec<Ident> = Vec::new();
     let mut queue_receiver: Vec<Ident> = Vec::new();
     let mut reconnect_sender: Vec<Ident> = Vec::new();
     let mut reconnect_receiver: Vec<Ident> = Vec::new();
+    let mut private_sender: Vec<Ident> = Vec::new();
+    let mut private_receiver: Vec<Ident> = Vec::new();
+    let mut spectate_sender: Vec<Ident> = Vec::new();
+    let mut spectate_receiver: Vec<Ident> = Vec::new();
     let mut action_sender: Vec<Ident> = Vec::new();
     let mut action_receiver: Vec<Ident> = Vec::new();
 
@@ -35,243 +45,434 @@ pub fn derive_queue_impl(input: TokenStream) -> TokenStream {
                 queue_receiver.push(format_ident!("{}_queue_recv", lower));
                 reconnect_sender.push(format_ident!("{}_reconnect", lower));
                 reconnect_receiver.push(format_ident!("{}_reconnect_recv", lower));
+                private_sender.push(format_ident!("{}_private", lower));
+                private_receiver.push(format_ident!("{}_private_recv", lower));
+                spectate_sender.push(format_ident!("{}_spectate", lower));
+                spectate_receiver.push(format_ident!("{}_spectate_recv", lower));
                 action_sender.push(format_ident!("{}_action", lower));
                 action_receiver.push(format_ident!("{}_action_recv", lower));
                 lobby_name.push(name);
                 lobby_type.push(ty);
+                lobby_size.push(size);
             }
         }
         _ => abort_call_site!("Only enums are supported."),
     };
 
+    #[cfg(feature = "server")]
     let sender = {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "server")] {
-                let sender_name = format_ident!("{queue}Sender");
-                let receivers_name = format_ident!("{queue}Receivers");
-                quote! {
-                    pub struct #receivers_name<U: ::ilium::server::data::UserData> {
-                        #(pub #queue_sender: ::ilium::server::send::Receiver<::ilium::server::send::QueueSignal<#component, U>>,)*
-                        #(pub #reconnect_sender: ::ilium::server::send::Receiver<::ilium::server::send::ReconnectSignal<#component>>,)*
-                        #(pub #action_sender: ::ilium::server::send::Receiver<::ilium::server::send::ActionSignal<#component>>,)*
-                    }
+        let sender_name = format_ident!("{queue}Sender");
+        let receivers_name = format_ident!("{queue}Receivers");
+        quote! {
+            pub struct #receivers_name<U: ::ilium::server::data::UserData> {
+                #(pub #queue_sender: ::ilium::server::send::Receiver<::ilium::server::send::QueueSignal<#component, U>>,)*
+                #(pub #reconnect_sender: ::ilium::server::send::Receiver<::ilium::server::send::ReconnectSignal<#component>>,)*
+                #(pub #private_sender: ::ilium::server::send::Receiver<::ilium::server::send::PrivateSignal<#component, U>>,)*
+                #(pub #spectate_sender: ::ilium::server::send::Receiver<::ilium::server::send::SpectateSignal<#component>>,)*
+                #(pub #action_sender: ::ilium::server::send::Receiver<::ilium::server::send::ActionSignal<#component>>,)*
+            }
 
-                    impl<U: ::ilium::server::data::UserData> ::ilium::server::send::Receivers for #receivers_name<U> {
-                        fn insert(self, app: &mut ::bevy::prelude::App) {
-                            let Self {
-                                #(#queue_sender,)*
-                                #(#reconnect_sender,)*
-                                #(#action_sender,)*
-                            } = self;
-                            #(app.insert_resource(#queue_sender);)*
-                            #(app.insert_resource(#reconnect_sender);)*
-                            #(app.insert_resource(#action_sender);)*
-                        }
-                    }
+            impl<U: ::ilium::server::data::UserData> ::ilium::server::send::Receivers for #receivers_name<U> {
+                fn insert(self, app: &mut ::bevy::prelude::App) {
+                    let Self {
+                        #(#queue_sender,)*
+                        #(#reconnect_sender,)*
+                        #(#private_sender,)*
+                        #(#spectate_sender,)*
+                        #(#action_sender,)*
+                    } = self;
+                    #(app.insert_resource(#queue_sender);)*
+                    #(app.insert_resource(#reconnect_sender);)*
+                    #(app.insert_resource(#private_sender);)*
+                    #(app.insert_resource(#spectate_sender);)*
+                    #(app.insert_resource(#action_sender);)*
+                }
+            }
 
-                    #[derive(Debug, ::bevy::prelude::Resource)]
-                    pub struct #sender_name<U: ::ilium::server::data::UserData> {
-                        pub pool: ::ilium::server::sqlx::Pool<U::DB>,
-                        #(pub #queue_sender: ::ilium::kanal::Sender<::ilium::server::send::QueueSignal<#component, U>>,)*
-                        #(pub #reconnect_sender: ::ilium::kanal::Sender<::ilium::server::send::ReconnectSignal<#component>>,)*
-                        #(pub #action_sender: ::ilium::kanal::Sender<::ilium::server::send::ActionSignal<#component>>,)*
-                    }
+            #[derive(Debug, ::bevy::prelude::Resource)]
+            pub struct #sender_name<U: ::ilium::server::data::UserData> {
+                pub pool: ::ilium::server::sqlx::Pool<U::DB>,
+                #(pub #queue_sender: ::ilium::kanal::Sender<::ilium::server::send::QueueSignal<#component, U>>,)*
+                #(pub #reconnect_sender: ::ilium::kanal::Sender<::ilium::server::send::ReconnectSignal<#component>>,)*
+                #(pub #private_sender: ::ilium::kanal::Sender<::ilium::server::send::PrivateSignal<#component, U>>,)*
+                #(pub #spectate_sender: ::ilium::kanal::Sender<::ilium::server::send::SpectateSignal<#component>>,)*
+                #(pub #action_sender: ::ilium::kanal::Sender<::ilium::server::send::ActionSignal<#component>>,)*
+            }
 
-                    impl<U: ::ilium::server::data::UserData> Clone for #sender_name<U> {
-                        fn clone(&self) -> Self {
-                            Self {
-                                pool: self.pool.clone(),
-                                #(#queue_sender: self.#queue_sender.clone(),)*
-                                #(#reconnect_sender: self.#reconnect_sender.clone(),)*
-                                #(#action_sender: self.#action_sender.clone(),)*
-                            }
-                        }
+            impl<U: ::ilium::server::data::UserData> Clone for #sender_name<U> {
+                fn clone(&self) -> Self {
+                    Self {
+                        pool: self.pool.clone(),
+                        #(#queue_sender: self.#queue_sender.clone(),)*
+                        #(#reconnect_sender: self.#reconnect_sender.clone(),)*
+                        #(#private_sender: self.#private_sender.clone(),)*
+                        #(#spectate_sender: self.#spectate_sender.clone(),)*
+                        #(#action_sender: self.#action_sender.clone(),)*
                     }
+                }
+            }
 
-                    impl<U, App> ::ilium::server::axum::extract::FromRef<::ilium::server::state::SenderAppState<#sender_name<U>, App>>
-                        for #sender_name<U>
-                    where
-                        U: ::ilium::server::data::UserData,
-                        App: ::ilium::server::state::AppState,
-                        ::ilium::server::leptos::prelude::LeptosOptions: ::ilium::server::axum::extract::FromRef<App>,
-                    {
-                        fn from_ref(input: &::ilium::server::state::SenderAppState<#sender_name<U>, App>) -> Self {
-                            input.sender.clone()
-                        }
+            impl<U, App> ::ilium::server::axum::extract::FromRef<::ilium::server::state::SenderAppState<#sender_name<U>, App>>
+                for #sender_name<U>
+            where
+                U: ::ilium::server::data::UserData,
+                App: ::ilium::server::state::AppState,
+                ::ilium::server::leptos::prelude::LeptosOptions: ::ilium::server::axum::extract::FromRef<App>,
+            {
+                fn from_ref(input: &::ilium::server::state::SenderAppState<#sender_name<U>, App>) -> Self {
+                    input.sender.clone()
+                }
+            }
+
+            impl<U: ::ilium::server::data::UserData> ::ilium::server::send::Sender for #sender_name<U> {
+                type Receivers = #receivers_name<Self::UserData>;
+                type Queue = #queue;
+                type UserData = U;
+                fn new(pool: ::ilium::server::sqlx::Pool<U::DB>) -> (Self, Self::Receivers) {
+                    #(let (#queue_sender, #queue_receiver) = ::ilium::kanal::unbounded();)*
+                    #(let (#reconnect_sender, #reconnect_receiver) = ::ilium::kanal::unbounded();)*
+                    #(let (#private_sender, #private_receiver) = ::ilium::kanal::unbounded();)*
+                    #(let (#spectate_sender, #spectate_receiver) = ::ilium::kanal::unbounded();)*
+                    #(let (#action_sender, #action_receiver) = ::ilium::kanal::unbounded();)*
+                    let sender = Self {
+                        pool,
+                        #(#queue_sender,)*
+                        #(#reconnect_sender,)*
+                        #(#private_sender,)*
+                        #(#spectate_sender,)*
+                        #(#action_sender,)*
+                    };
+                    let receivers = Self::Receivers {
+                        #(#queue_sender: ::ilium::server::send::Receiver(#queue_receiver),)*
+                        #(#reconnect_sender: ::ilium::server::send::Receiver(#reconnect_receiver),)*
+                        #(#private_sender: ::ilium::server::send::Receiver(#private_receiver),)*
+                        #(#spectate_sender: ::ilium::server::send::Receiver(#spectate_receiver),)*
+                        #(#action_sender: ::ilium::server::send::Receiver(#action_receiver),)*
+                    };
+                    (sender, receivers)
+                }
+                fn pool(&self) -> &::ilium::server::sqlx::Pool<U::DB> {
+                    &self.pool
+                }
+                fn send(
+                    &self,
+                    account: ::ilium::server::account::Account,
+                    msg: Msg<Self::Queue>,
+                    send_frame: ::ilium::server::send::SendFrame,
+                    ping: ::ilium::server::time::Ping,
+                ) -> impl ::core::future::Future<Output = ::eyre::Result<()>> + Send {
+                    async move {
+                        let ::ilium::session::msg::Msg { queue, msg_type, .. } = msg;
+                        let _phantom = std::marker::PhantomData;
+                        match (msg_type, queue) {
+                            #(
+                                (MsgType::Join, #queue::#variant_name) => {
+                                    let user_data = Self::UserData::query(&self.pool, &account).await?;
+                                    let avoid = ::ilium::server::avoid::Avoid::query::<Self::UserData>(&self.pool, &account).await?;
+                                    self.#queue_sender.send(::ilium::server::send::QueueSignal::Join {
+                                        account,
+                                        send_frame,
+                                        user_data,
+                                        avoid,
+                                        ping,
+                                        _phantom,
+                                    })
+                                }
+                                (MsgType::Reconnect, #queue::#variant_name) =>
+                                    self.#reconnect_sender.send(::ilium::server::send::ReconnectSignal {
+                                        account,
+                                        ping,
+                                        send_frame,
+                                        _phantom,
+                                    }),
+                                (MsgType::Accept(entropy), #queue::#variant_name)=>
+                                    self.#queue_sender.send(::ilium::server::send::QueueSignal::Accept { account, entropy, _phantom }),
+                                (MsgType::Vote(settings), #queue::#variant_name) =>
+                                    self.#queue_sender.send(::ilium::server::send::QueueSignal::Vote { account, settings, _phantom }),
+                                (MsgType::Leave, #queue::#variant_name) => {
+                                    self.#spectate_sender.send(::ilium::server::send::SpectateSignal::Leave { account, _phantom })?;
+                                    self.#queue_sender.send(::ilium::server::send::QueueSignal::Leave { account, _phantom })
+                                }
+                                (MsgType::Create, #queue::#variant_name) => {
+                                    let user_data = Self::UserData::query(&self.pool, &account).await?;
+                                    self.#private_sender.send(::ilium::server::send::PrivateSignal::Create {
+                                        account,
+                                        send_frame,
+                                        user_data,
+                                        ping,
+                                        _phantom,
+                                    })
+                                }
+                                (MsgType::JoinCode(code), #queue::#variant_name) => {
+                                    let user_data = Self::UserData::query(&self.pool, &account).await?;
+                                    self.#private_sender.send(::ilium::server::send::PrivateSignal::Join {
+                                        code,
+                                        account,
+                                        send_frame,
+                                        user_data,
+                                        ping,
+                                        _phantom,
+                                    })
+                                }
+                                (MsgType::Start, #queue::#variant_name) =>
+                                    self.#private_sender.send(::ilium::server::send::PrivateSignal::Start { account, _phantom }),
+                                (MsgType::Spectate(session), #queue::#variant_name) =>
+                                    self.#spectate_sender.send(::ilium::server::send::SpectateSignal::Join {
+                                        account,
+                                        session,
+                                        ping,
+                                        send_frame,
+                                        _phantom,
+                                    }),
+                                (MsgType::Action { action, id }, #queue::#variant_name) =>
+                                    self.#action_sender.send(::ilium::server::send::ActionSignal { account, action, id }),
+                            )*
+                        }?;
+                        Ok(())
                     }
+                }
+            }
+        }
+    };
+    #[cfg(not(feature = "server"))]
+    let sender = proc_macro2::TokenStream::default();
+
+    #[cfg(feature = "client")]
+    let client = {
+        let client_name = format_ident!("{queue}Client");
+        let native_name = format_ident!("{queue}NativeClient");
+        quote! {
+            /// Typed connection to the server for this queue enum from a native program
+            #[cfg(not(target_arch = "wasm32"))]
+            #[derive(Clone)]
+            #vis struct #native_name(pub ::ilium::client::native::Client<#queue>);
 
-                    impl<U: ::ilium::server::data::UserData> ::ilium::server::send::Sender for #sender_name<U> {
-                        type Receivers = #receivers_name<Self::UserData>;
-                        type Queue = #queue;
-                        type UserData = U;
-                        fn new(pool: ::ilium::server::sqlx::Pool<U::DB>) -> (Self, Self::Receivers) {
-                            #(let (#queue_sender, #queue_receiver) = ::ilium::kanal::unbounded();)*
-                            #(let (#reconnect_sender, #reconnect_receiver) = ::ilium::kanal::unbounded();)*
-                            #(let (#action_sender, #action_receiver) = ::ilium::kanal::unbounded();)*
-                            let sender = Self {
-                                pool,
-                                #(#queue_sender,)*
-                                #(#reconnect_sender,)*
-                                #(#action_sender,)*
-                            };
-                            let receivers = Self::Receivers {
-                                #(#queue_sender: ::ilium::server::send::Receiver(#queue_receiver),)*
-                                #(#reconnect_sender: ::ilium::server::send::Receiver(#reconnect_receiver),)*
-                                #(#action_sender: ::ilium::server::send::Receiver(#action_receiver),)*
-                            };
-                            (sender, receivers)
-                        }
-                        fn send(
-                            &self,
-                            msg: Msg<Self::Queue>,
-                            ip: ::std::net::SocketAddr,
-                            send_frame: ::ilium::server::send::SendFrame,
-                            ping: ::ilium::server::time::Ping,
-                        ) -> impl ::core::future::Future<Output = ::eyre::Result<()>> + Send {
-                            async move {
-                                let ::ilium::session::msg::Msg { token, queue, msg_type } = msg;
-                                let account = ::ilium::server::auth::auth(token, ip);
-                                let _phantom = std::marker::PhantomData;
-                                match (msg_type, queue) {
-                                    #(
-                                        (MsgType::Join, #queue::#variant_name) => {
-                                            let user_data = Self::UserData::query(&self.pool, &account).await?;
-                                            self.#queue_sender.send(::ilium::server::send::QueueSignal::Join {
-                                                account,
-                                                send_frame,
-                                                user_data,
-                                                ping,
-                                                _phantom,
-                                            })
-                                        }
-                                        (MsgType::Reconnect, #queue::#variant_name) =>
-                                            self.#reconnect_sender.send(::ilium::server::send::ReconnectSignal {
-                                                account,
-                                                ping,
-                                                send_frame,
-                                                _phantom,
-                                            }),
-                                        (MsgType::Accept, #queue::#variant_name)=>
-                                            self.#queue_sender.send(::ilium::server::send::QueueSignal::Accept { account, _phantom }),
-                                        (MsgType::Leave, #queue::#variant_name) =>
-                                            self.#queue_sender.send(::ilium::server::send::QueueSignal::Leave { account, _phantom }),
-                                        (MsgType::Action(action), #queue::#variant_name) =>
-                                            self.#action_sender.send(::ilium::server::send::ActionSignal { account, action }),
-                                    )*
-                                }?;
-                                Ok(())
-                            }
-                        }
+            #[cfg(not(target_arch = "wasm32"))]
+            impl ::core::ops::Deref for #native_name {
+                type Target = ::ilium::client::native::Client<#queue>;
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            impl #native_name {
+                /// Connect to the server's websocket endpoint, e.g. `ws://localhost:3000/ws`
+                pub async fn connect(
+                    url: &str,
+                    token: ::ilium::session::ClientToken,
+                ) -> ::eyre::Result<(Self, ::ilium::client::native::Updates<#queue>)> {
+                    let (client, updates) = ::ilium::client::native::Client::connect(url, token).await?;
+                    Ok((Self(client), updates))
+                }
+                /// Connect to a server accepting raw TCP clients, e.g. `127.0.0.1:3001`
+                pub async fn connect_tcp(
+                    addr: &str,
+                    token: ::ilium::session::ClientToken,
+                ) -> ::eyre::Result<(Self, ::ilium::client::native::Updates<#queue>)> {
+                    let (client, updates) = ::ilium::client::native::Client::connect_tcp(addr, token).await?;
+                    Ok((Self(client), updates))
+                }
+                /// Returns the id the action was sent with, which its reply carries
+                pub async fn act(&self, queue: #queue, action: #action) -> ::eyre::Result<u64> {
+                    self.0.action(queue, action).await
+                }
+            }
+
+            /// Typed connection to the server for this queue enum
+            #[derive(Clone, Copy)]
+            #vis struct #client_name(pub ::ilium::client::web::Handle<#queue>);
+
+            impl ::core::ops::Deref for #client_name {
+                type Target = ::ilium::client::web::Handle<#queue>;
+                fn deref(&self) -> &Self::Target {
+                    &self.0
+                }
+            }
+
+            impl #client_name {
+                /// Connect to the server at `url` for the lifetime of the current reactive owner
+                pub fn connect(url: &str, token: ::ilium::session::ClientToken) -> Self {
+                    Self(::ilium::client::web::ws_handle(url, token))
+                }
+                pub fn join(&self, queue: #queue) {
+                    self.0.join(queue);
+                }
+                pub fn create(&self, queue: #queue) {
+                    self.0.create(queue);
+                }
+                pub fn join_code(&self, queue: #queue, code: ::ilium::session::InviteCode) {
+                    self.0.join_code(queue, code);
+                }
+                pub fn spectate(&self, queue: #queue, session: u64) {
+                    self.0.spectate(queue, session);
+                }
+                // the calls below apply to the queue most recently joined, and are
+                // dropped if there is none
+                pub fn accept(&self) {
+                    if let Some(queue) = self.0.queue() {
+                        self.0.accept(queue);
+                    }
+                }
+                pub fn accept_with(&self, entropy: ::ilium::session::Entropy) {
+                    if let Some(queue) = self.0.queue() {
+                        self.0.accept_with(queue, entropy);
+                    }
+                }
+                pub fn vote(&self, settings: ::ilium::session::SettingsOf<#action>) {
+                    if let Some(queue) = self.0.queue() {
+                        self.0.vote(queue, settings);
                     }
                 }
-            } else if #[cfg(feature = "client")] {
-                proc_macro2::TokenStream::default()
-            } else {
-                proc_macro2::TokenStream::default()
+                pub fn start(&self) {
+                    if let Some(queue) = self.0.queue() {
+                        self.0.start(queue);
+                    }
+                }
+                pub fn leave(&self) {
+                    if let Some(queue) = self.0.queue() {
+                        self.0.leave(queue);
+                    }
+                }
+                /// Returns the id the action was sent with, which its reply carries
+                pub fn act(&self, action: #action) -> Option<u64> {
+                    let queue = self.0.queue()?;
+                    Some(self.0.action(queue, action))
+                }
             }
         }
     };
+    #[cfg(not(feature = "client"))]
+    let client = proc_macro2::TokenStream::default();
 
+    #[cfg(feature = "server")]
     let register = {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "server")] {
-                quote! {
-                    impl ::ilium::server::app::Register for #queue {
-                        fn register<U: ::ilium::server::data::UserData>(app: &mut ::bevy::prelude::App) {
-                            #(
-                                app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::process_queue::<#component, U>);
-                                app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::reconnect::<#component>);
-                                app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::matchmake::<#component, U>);
-                                app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::init_session::<#component>);
-                                app.add_systems(::bevy::prelude::Update, ::ilium::server::update::update_client::<#component>);
-                                app.add_systems(::bevy::prelude::Update, ::ilium::server::update::process_actions::<#component>);
-                            )*
-                        }
-                    }
+        quote! {
+            impl ::ilium::server::app::Register for #queue {
+                fn register<U: ::ilium::server::data::UserData>(app: &mut ::bevy::prelude::App) {
+                    #(
+                        app.init_resource::<::ilium::server::broadcast::BroadcastConfig<#component>>();
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::process_queue::<#component, U>);
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::reconnect::<#component>);
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::matchmake::<#component, U>);
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::update_lobby::<#component, U>);
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::matchmaking::init_session::<#component>);
+                        app.add_systems(::bevy::prelude::Update, ::bevy::prelude::IntoScheduleConfigs::chain((
+                            ::ilium::server::update::process_actions::<#component>,
+                            ::ilium::server::context::send_events::<#component>,
+                            ::ilium::server::broadcast::mark_changed::<#component>,
+                            ::ilium::server::update::update_client::<#component>,
+                            ::ilium::server::context::end_sessions::<#component>,
+                        )));
+                        app.add_systems(::bevy::prelude::Update, ::bevy::prelude::IntoScheduleConfigs::chain((
+                            ::ilium::server::private::process_private::<#component, U>,
+                            ::ilium::server::private::update_private::<#component, U>,
+                        )));
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::spectate::spectate::<#component>);
+                        app.add_systems(::bevy::prelude::Update, ::ilium::server::spectate::update_spectators::<#component>);
+                    )*
                 }
-            } else if #[cfg(feature = "client")] {
-                proc_macro2::TokenStream::default()
-            } else {
-                proc_macro2::TokenStream::default()
             }
         }
     };
+    #[cfg(not(feature = "server"))]
+    let register = proc_macro2::TokenStream::default();
 
+    #[cfg(feature = "server")]
     let queue_derive = {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "server")] {
-                quote! {
-                    impl ::ilium::server::Queue for #queue {
-                        fn insert(&self, ec: &mut ::bevy::ecs::system::EntityCommands) {
-                            match self {
-                                #(Self::#variant_name => ec.insert(#component),)*
-                            };
-                        }
-                    }
+        quote! {
+            impl ::ilium::server::Queue for #queue {
+                fn insert(&self, ec: &mut ::bevy::ecs::system::EntityCommands) {
+                    match self {
+                        #(Self::#variant_name => ec.insert(#component),)*
+                    };
+                }
+            }
 
-                    #(
-                        #[derive(Clone, Default, Debug, ::bevy::prelude::Component)]
-                        pub struct #component;
+            #(
+                #[derive(Clone, Default, Debug, ::bevy::prelude::Component)]
+                pub struct #component;
 
-                        #[derive(Clone, ::bevy::prelude::Component)]
-                        pub struct #lobby_name(#lobby_type);
+                #[derive(Clone, ::bevy::prelude::Component)]
+                pub struct #lobby_name(#lobby_type);
 
-                        impl<'a> std::convert::TryFrom<&'a [::bevy::prelude::Entity]> for #lobby_name {
-                            type Error = std::array::TryFromSliceError;
-                            fn try_from(v: &[::bevy::prelude::Entity]) -> Result<Self, Self::Error> {
-                                let list: #lobby_type = v.try_into()?;
-                                Ok(#lobby_name(list))
-                            }
-                        }
+                impl<'a> std::convert::TryFrom<&'a [::bevy::prelude::Entity]> for #lobby_name {
+                    type Error = std::array::TryFromSliceError;
+                    fn try_from(v: &[::bevy::prelude::Entity]) -> Result<Self, Self::Error> {
+                        let list: #lobby_type = v.try_into()?;
+                        Ok(#lobby_name(list))
+                    }
+                }
 
-                        impl ::ilium::server::Lobby for #lobby_name {
-                            fn len(&self) -> usize {
-                                self.0.len()
-                            }
-                            fn entities(&self) -> impl Iterator<Item = ::bevy::prelude::Entity> {
-                                let Self(list) = self;
-                                list.iter().copied()
-                            }
-                        }
+                impl ::ilium::server::Lobby for #lobby_name {
+                    fn capacity() -> usize {
+                        #lobby_size
+                    }
+                    fn len(&self) -> usize {
+                        self.0.len()
+                    }
+                    fn entities(&self) -> impl Iterator<Item = ::bevy::prelude::Entity> {
+                        let Self(list) = self;
+                        list.iter().copied()
+                    }
+                }
 
-                        impl ::ilium::server::QueueComponent for #component {
-                            type Queue = #queue;
-                            type Lobby = #lobby_name;
-                            type Action = #action;
-                            type Shared = <#action as ::ilium::Action>::Shared;
-                            type User = <#action as ::ilium::Action>::User;
-                            fn info<S: ::ilium::session::AsState<
-                                Shared = <#action as ::ilium::Action>::Shared,
-                                User = <#action as ::ilium::Action>::User,
-                            >>(
-                                index: S::Index,
-                                state: &S,
-                            ) -> ::ilium::session::Info<S::User, S::Shared>
-                            where
-                                S::Index: ::ilium::server::AsIndex
-                            {
-                                ::ilium::session::Info {
-                                    users: Self::User::info(index, state),
-                                    shared: Self::Shared::info(index, state),
-                                    index: <S::Index as ::ilium::server::AsIndex>::to_index(&index),
-                                }
-                            }
-                        }
-                    )*
+                impl ::ilium::server::QueueComponent for #component {
+                    type Queue = #queue;
+                    type Lobby = #lobby_name;
+                    type Action = #action;
+                    type Shared = <#action as ::ilium::Action>::Shared;
+                    type User = <#action as ::ilium::Action>::User;
+                    fn info<S: ::ilium::session::AsState<
+                        Shared = <#action as ::ilium::Action>::Shared,
+                        User = <#action as ::ilium::Action>::User,
+                    >>(
+                        index: S::Index,
+                        state: &S,
+                    ) -> ::ilium::session::Info<S::User, S::Shared>
+                    where
+                        S::Index: ::ilium::server::AsIndex
+                    {
+                        ::ilium::session::Info::new(
+                            Self::User::info(index, state),
+                            Self::Shared::info(index, state),
+                            <S::Index as ::ilium::server::AsIndex>::to_index(&index),
+                        )
+                    }
                 }
-            } else {
-                proc_macro2::TokenStream::default()
-            }
+            )*
         }
     };
+    #[cfg(not(feature = "server"))]
+    let queue_derive = proc_macro2::TokenStream::default();
 
     quote! {
         #sender
         #register
         #queue_derive
+        #client
+
+        /// Everything the server sends about a place in this queue
+        #vis type #state_info_name = ::ilium::session::StateInfo<#action>;
+        /// A client's view of a running session in this queue
+        #vis type #session_info_name = ::ilium::session::Info<
+            <#action as ::ilium::Action>::User,
+            <#action as ::ilium::Action>::Shared,
+        >;
 
         impl ::ilium::session::AsQueue for #queue {
             type Action = #action;
+            const FINGERPRINT: u64 = {
+                use ::ilium::session::{Action, Schema, SharedState, UserState, schema::combine};
+                type Shared = <#action as Action>::Shared;
+                type User = <#action as Action>::User;
+                let hash = combine(#queue_fingerprint, <#action as Schema>::FINGERPRINT);
+                let hash = combine(hash, <<#action as Action>::Error as Schema>::FINGERPRINT);
+                let hash = combine(hash, <<#action as Action>::Event as Schema>::FINGERPRINT);
+                let hash = combine(hash, <<Shared as SharedState>::Info as Schema>::FINGERPRINT);
+                let hash = combine(hash, <<User as UserState>::Info as Schema>::FINGERPRINT);
+                combine(hash, <<Shared as SharedState>::Settings as Schema>::FINGERPRINT)
+            };
         }
     }
     .into()
@@ -0,0 +1,90 @@
+use proc_macro::TokenStream;
+use proc_macro_error::abort_call_site;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::*;
+
+fn fields_shape(fields: &Fields) -> String {
+    let field = fields.iter().map(|field| {
+        let ty = &field.ty;
+        match &field.ident {
+            Some(name) => format!("{name}:{}", quote!(#ty)),
+            None => quote!(#ty).to_string(),
+        }
+    });
+    let field: Vec<String> = field.collect();
+    match fields {
+        Fields::Named(_) => format!("{{{}}}", field.join(",")),
+        Fields::Unnamed(_) => format!("({})", field.join(",")),
+        Fields::Unit => String::new(),
+    }
+}
+
+/// Textual shape of a type definition, stable across builds of the same source
+pub fn shape(ident: &Ident, data: &Data) -> String {
+    match data {
+        Data::Struct(DataStruct { fields, .. }) => {
+            format!("struct {ident}{}", fields_shape(fields))
+        }
+        Data::Enum(DataEnum { variants, .. }) => {
+            let variants: Vec<String> = variants
+                .iter()
+                .map(|v| format!("{}{}", v.ident, fields_shape(&v.fields)))
+                .collect();
+            format!("enum {ident}{{{}}}", variants.join(","))
+        }
+        Data::Union(_) => abort_call_site!("Unions are not supported."),
+    }
+}
+
+/// Fingerprint of a shape with the fingerprints of its field types folded in, in order
+pub fn fold(shape: &str, types: &[&Type]) -> proc_macro2::TokenStream {
+    let fingerprint = session::schema::fingerprint(shape.as_bytes());
+    let field = types
+        .iter()
+        .map(|ty| quote_spanned!(ty.span()=> <#ty as ::ilium::session::Schema>::FINGERPRINT));
+    quote! {{
+        let hash = #fingerprint;
+        #(let hash = ::ilium::session::schema::combine(hash, #field);)*
+        hash
+    }}
+}
+
+/// `ty: Schema` for each field type, pointing a missing impl at the field
+pub fn bounds<'a>(
+    types: impl IntoIterator<Item = &'a Type>,
+) -> impl Iterator<Item = proc_macro2::TokenStream> {
+    types
+        .into_iter()
+        .map(|ty| quote_spanned!(ty.span()=> #ty: ::ilium::session::Schema))
+}
+
+fn field_types(data: &Data) -> Vec<&Type> {
+    match data {
+        Data::Struct(DataStruct { fields, .. }) => fields.iter().map(|f| &f.ty).collect(),
+        Data::Enum(DataEnum { variants, .. }) => variants
+            .iter()
+            .flat_map(|v| v.fields.iter().map(|f| &f.ty))
+            .collect(),
+        Data::Union(_) => abort_call_site!("Unions are not supported."),
+    }
+}
+
+pub fn derive_schema_impl(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    let name = &ast.ident;
+    let types = field_types(&ast.data);
+    let fingerprint = fold(&shape(name, &ast.data), &types);
+    let mut generics = ast.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for bound in bounds(types.iter().copied()) {
+        where_clause.predicates.push(parse_quote!(#bound));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::ilium::session::Schema for #name #ty_generics #where_clause {
+            const FINGERPRINT: u64 = #fingerprint;
+        }
+    }
+    .into()
+}
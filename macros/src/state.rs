@@ -1,8 +1,8 @@
 use crate::util::*;
 use proc_macro::TokenStream;
 use proc_macro_error::abort_call_site;
-use quote::{format_ident, quote};
-use syn::*;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{spanned::Spanned, *};
 
 enum IliumFieldInfo {
     Open { name: Ident, ty: Type },
@@ -21,19 +21,8 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
         name_value(&ast.attrs, "shared")
             .unwrap_or_else(|| abort_call_site!("Could not find `shared` attribute"))
     };
-    let init = name_value::<Path>(&ast.attrs, "init")
-        .map(|path| {
-            if is_shared {
-                quote!(#path(seed))
-            } else {
-                quote!(#path(shared, users))
-            }
-        })
-        .unwrap_or(if is_shared {
-            quote!(Default::default())
-        } else {
-            quote!(vec![Default::default(); users])
-        });
+    let init_path = name_value::<Path>(&ast.attrs, "init");
+    let settings: Type = name_value(&ast.attrs, "settings").unwrap_or(parse_quote!(()));
     let mut open_name: Vec<Ident> = Vec::new();
     let mut open_type: Vec<Type> = Vec::new();
     let mut hidden_name: Vec<Ident> = Vec::new();
@@ -41,6 +30,7 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
     let mut hidden_fn: Vec<Ident> = Vec::new();
     let mut private_name: Vec<Ident> = Vec::new();
     let mut private_type: Vec<Type> = Vec::new();
+    let mut rng_name: Option<Ident> = None;
 
     match ast.data {
         Data::Struct(DataStruct {
@@ -50,6 +40,15 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
             for field in named.iter() {
                 let name = field.ident.clone().unwrap();
                 let ty = field.ty.clone();
+                if name_value::<LitBool>(&field.attrs, "rng").is_some_and(|b| b.value) {
+                    if !is_shared {
+                        abort_call_site!("`rng` is only supported on shared state");
+                    }
+                    if rng_name.replace(name).is_some() {
+                        abort_call_site!("Only one field can be marked `rng`");
+                    }
+                    continue;
+                }
                 let info = if name_value::<LitBool>(&field.attrs, "open").is_some_and(|b| b.value) {
                     IliumFieldInfo::Open { ty, name }
                 } else if let Some(hidden) = name_value::<HiddenFn>(&field.attrs, "hidden") {
@@ -80,6 +79,106 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
         }
         _ => abort_call_site!("Only structs are supported."),
     };
+    let init = match (init_path, &rng_name) {
+        (Some(path), None) if is_shared => quote!(#path(seed, settings)),
+        (Some(path), None) => quote!(#path(shared, users)),
+        (None, None) if is_shared => quote!(Default::default()),
+        (None, None) => quote!(vec![Default::default(); users]),
+        // a separate stream from the session's own rng, drawn from the same seed
+        (path, Some(rng)) => {
+            let rng_init = quote! {
+                let mut rng = <::ilium::session::SessionRng as ::ilium::session::rand_core::SeedableRng>::from_seed(seed);
+                rng.set_stream(1);
+            };
+            match path {
+                Some(path) => quote! {
+                    #rng_init
+                    let mut state: Self = #path(seed, settings);
+                    state.#rng = rng;
+                    state
+                },
+                None => quote! {
+                    #rng_init
+                    Self {
+                        #rng: rng,
+                        #(#open_name: Default::default(),)*
+                        #(#hidden_name: Default::default(),)*
+                        #(#private_name: Default::default(),)*
+                    }
+                },
+            }
+        }
+    };
+    let info_shape = {
+        let mut fields: Vec<String> = Vec::new();
+        for (name, ty) in open_name.iter().zip(open_type.iter()) {
+            fields.push(format!("{name}:{}", quote!(#ty)));
+        }
+        for (name, ty) in hidden_name.iter().zip(hidden_type.iter()) {
+            fields.push(format!("{name}:{}", quote!(#ty)));
+        }
+        if !is_shared {
+            for (name, ty) in private_name.iter().zip(private_type.iter()) {
+                fields.push(format!("{name}:Option<{}>", quote!(#ty)));
+            }
+        }
+        format!("struct {info_name}{{{}}}", fields.join(","))
+    };
+    let delta_name = format_ident!("{info_name}Delta");
+    let mut info_field: Vec<&Ident> = open_name.iter().chain(hidden_name.iter()).collect();
+    let mut info_type: Vec<Type> = open_type
+        .iter()
+        .chain(hidden_type.iter())
+        .cloned()
+        .collect();
+    if !is_shared {
+        info_field.extend(private_name.iter());
+        info_type.extend(private_type.iter().map(|ty| parse_quote!(Option<#ty>)));
+    }
+    let info_fingerprint = crate::schema::fold(&info_shape, &info_type.iter().collect::<Vec<_>>());
+    let fingerprinted = crate::schema::bounds(info_type.iter());
+    // deltas compare each field with what was last sent, so point a missing `PartialEq`
+    // at the field's own type rather than at the derive
+    let compared = open_type
+        .iter()
+        .chain(hidden_type.iter())
+        .chain(private_type.iter())
+        .map(|ty| quote_spanned!(ty.span()=> #ty: ::core::cmp::PartialEq));
+    let diff = quote! {
+        #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
+        pub struct #delta_name {
+            #(pub #info_field: Option<#info_type>,)*
+        }
+
+        impl ::ilium::session::Diff for #info_name
+        where
+            #(#compared,)*
+        {
+            type Delta = #delta_name;
+            fn diff(&self, old: &Self) -> Option<Self::Delta> {
+                let delta = #delta_name {
+                    #(#info_field: (self.#info_field != old.#info_field).then(|| self.#info_field.clone()),)*
+                };
+                (false #(|| delta.#info_field.is_some())*).then_some(delta)
+            }
+            fn apply(&mut self, delta: Self::Delta) {
+                let #delta_name { #(#info_field,)* } = delta;
+                #(
+                    if let Some(value) = #info_field {
+                        self.#info_field = value;
+                    }
+                )*
+            }
+        }
+    };
+    let schema = quote! {
+        impl ::ilium::session::Schema for #info_name
+        where
+            #(#fingerprinted,)*
+        {
+            const FINGERPRINT: u64 = #info_fingerprint;
+        }
+    };
     let info = if is_shared {
         quote! {
             #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
@@ -98,40 +197,42 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
             }
         }
     };
+    #[cfg(feature = "server")]
     let timer = {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "server")] {
-                let timers: Vec<Ident> = name_value::<CommaSeparated<Ident>>(&ast.attrs, "timers").map(|t| t.0).unwrap_or_default();
-                quote! {
-                    impl ::ilium::server::AsStopwatch for #state {
-                        fn pause(&mut self) {
-                            #(self.#timers.pause();)*
-                        }
-                        fn unpause(&mut self) {
-                            #(self.#timers.unpause();)*
-                        }
-                        fn reset(&mut self) {
-                            #(self.#timers.reset();)*
-                        }
-                        fn tick(&mut self, delta: ::std::time::Duration) {
-                            #(self.#timers.tick(delta);)*
-                        }
-                    }
+        let timers: Vec<Ident> = name_value::<CommaSeparated<Ident>>(&ast.attrs, "timers")
+            .map(|t| t.0)
+            .unwrap_or_default();
+        quote! {
+            impl ::ilium::server::AsStopwatch for #state {
+                fn pause(&mut self) {
+                    #(self.#timers.pause();)*
+                }
+                fn unpause(&mut self) {
+                    #(self.#timers.unpause();)*
+                }
+                fn reset(&mut self) {
+                    #(self.#timers.reset();)*
+                }
+                fn tick(&mut self, delta: ::std::time::Duration) {
+                    #(self.#timers.tick(delta);)*
                 }
-            } else {
-                proc_macro2::TokenStream::default()
             }
         }
     };
+    #[cfg(not(feature = "server"))]
+    let timer = proc_macro2::TokenStream::default();
 
     if is_shared {
         quote! {
             #info
+            #schema
+            #diff
             #timer
 
             impl ::ilium::session::SharedState for #state {
                 type Info = #info_name;
                 type User = #other;
+                type Settings = #settings;
                 fn info<S: ::ilium::session::AsState<Shared = #state>>(index: S::Index, state: &S) -> Self::Info {
                     let shared = state.shared();
                     let shared: &#state = ::core::borrow::Borrow::borrow(&shared);
@@ -140,7 +241,7 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
                         #(#hidden_name: #hidden_fn(index, state),)*
                     }
                 }
-                fn init(seed: [u8; 32]) -> Self {
+                fn init(seed: [u8; 32], settings: Self::Settings) -> Self {
                     #init
                 }
             }
@@ -149,6 +250,8 @@ pub fn derive_state_impl(input: TokenStream, is_shared: bool) -> TokenStream {
     } else {
         quote! {
             #info
+            #schema
+            #diff
             #timer
 
             impl ::ilium::session::UserState for #state {
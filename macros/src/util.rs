@@ -18,8 +18,10 @@ impl Parse for HiddenFn {
     }
 }
 
+#[cfg(feature = "server")]
 pub struct CommaSeparated<T: Parse>(pub Vec<T>);
 
+#[cfg(feature = "server")]
 impl<T: Parse> Parse for CommaSeparated<T> {
     fn parse(input: ParseStream) -> Result<Self> {
         let punctuated = Punctuated::<T, Token![,]>::parse_terminated(input)?;
@@ -28,6 +30,7 @@ impl<T: Parse> Parse for CommaSeparated<T> {
     }
 }
 
+#[cfg(feature = "server")]
 impl<T: Parse> IntoIterator for CommaSeparated<T> {
     type Item = T;
     type IntoIter = ::std::vec::IntoIter<T>;
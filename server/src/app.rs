@@ -1,18 +1,28 @@
 use crate::{
     account::AccountMap,
+    avoid::{AvoidRefresh, refresh_avoid},
+    backpressure::{Backpressure, BackpressurePolicy},
+    ban::Bans,
+    broadcast::BroadcastConfig,
+    context::end_sessions,
     data::UserData,
+    history::MatchHistory,
     matchmaking::{matchmake, process_queue, reconnect},
     queue::*,
+    replay::{ReplayRecorder, finish_recordings, save_recording, save_recordings_on_exit},
     send::{Receivers, Sender},
+    spectate::SpectatorDelay,
     state::{AppState, SenderAppState},
     time::*,
+    transport::{Transport, listen},
+    update::update_client,
     ws::ws_handler,
 };
-use axum::{Router, extract::FromRef, routing::any};
-use bevy::prelude::{IntoScheduleConfigs, PluginGroup, System, Update};
+use axum::{Extension, Router, extract::FromRef, routing::any};
+use bevy::prelude::{IntoScheduleConfigs, Last, PluginGroup, System, Update};
 use leptos::{IntoView, logging, prelude::*};
 use leptos_axum::{LeptosRoutes, file_and_error_handler};
-use session::Action;
+use session::{Action, Checksum};
 use sqlx::*;
 
 pub trait Register {
@@ -22,8 +32,15 @@ pub trait Register {
 pub struct App {
     axum_router: Router,
     bevy_app: bevy::prelude::App,
+    history: Task,
+    bans: Task,
+    avoid: Task,
+    transports: Vec<Task>,
+    recorders: Vec<Task>,
 }
 
+type Task = core::pin::Pin<Box<dyn core::future::Future<Output = ()> + Send>>;
+
 impl App {
     pub fn new<Q, U, S, A, IV>(
         state: A,
@@ -39,8 +56,12 @@ impl App {
         IV: IntoView + 'static,
         LeptosOptions: FromRef<A> + FromRef<SenderAppState<S, A>>,
     {
+        let (history, history_task) = MatchHistory::new::<U>(pool.clone());
+        let (bans, bans_task) = Bans::new::<U>(pool.clone());
+        let (avoid, avoid_task) = AvoidRefresh::new::<U>(pool.clone());
         let (sender, receivers) = S::new(pool);
-        let state = SenderAppState::from_sender_and_options(sender, state);
+        let backpressure = Backpressure::default();
+        let state = SenderAppState::from_sender_and_options(sender.clone(), state);
         let axum_router = Router::new()
             .leptos_routes(&state, routes, {
                 let leptos_options = LeptosOptions::from_ref(&state);
@@ -48,7 +69,9 @@ impl App {
             })
             .route("/ws", any(ws_handler::<S>))
             .fallback(file_and_error_handler::<SenderAppState<S, A>, IV>(shell))
-            .with_state(state);
+            .with_state(state)
+            .layer(Extension(backpressure.clone()))
+            .layer(Extension(bans.clone()));
         let mut bevy_app = bevy::prelude::App::new();
         bevy_app
             .add_plugins(bevy::prelude::MinimalPlugins.set(
@@ -56,12 +79,23 @@ impl App {
                     1.0 / 60.0,
                 )),
             ))
-            .insert_resource(AccountMap::default());
+            .insert_resource(AccountMap::default())
+            .insert_resource(history)
+            .insert_resource(backpressure)
+            .insert_resource(bans)
+            .insert_resource(avoid)
+            .insert_resource(sender)
+            .add_systems(Update, refresh_avoid);
         Q::register::<U>(&mut bevy_app);
         receivers.insert(&mut bevy_app);
         Self {
             axum_router,
             bevy_app,
+            history: history_task,
+            bans: bans_task,
+            avoid: avoid_task,
+            transports: Vec::new(),
+            recorders: Vec::new(),
         }
     }
     pub fn add_time<T: AsStopwatch>(&mut self) {
@@ -96,6 +130,53 @@ impl App {
         self.bevy_app.add_systems(Update, reconnect::<QC>);
         self
     }
+    pub fn backpressure(self, policy: BackpressurePolicy) -> Self {
+        self.bevy_app
+            .world()
+            .resource::<Backpressure>()
+            .set_policy(policy);
+        self
+    }
+    /// Also accept clients over `transport`, alongside the websocket route
+    pub fn add_transport<S: Sender, T: Transport>(mut self, transport: T) -> Self {
+        let world = self.bevy_app.world();
+        let sender = world.resource::<S>().clone();
+        let backpressure = world.resource::<Backpressure>().clone();
+        let bans = world.resource::<Bans>().clone();
+        self.transports
+            .push(Box::pin(listen(transport, sender, backpressure, bans)));
+        self
+    }
+    /// Record every session of the queue to a replay file in `dir`
+    pub fn record_replays<QC: QueueComponent>(mut self, dir: impl Into<std::path::PathBuf>) -> Self
+    where
+        QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+        QC::Shared: Checksum,
+        QC::User: Checksum,
+    {
+        let (recorder, task) = ReplayRecorder::<QC>::new(dir);
+        self.bevy_app
+            .insert_resource(recorder)
+            .add_systems(
+                Update,
+                finish_recordings::<QC>
+                    .after(update_client::<QC>)
+                    .before(end_sessions::<QC>),
+            )
+            .add_systems(Last, save_recordings_on_exit::<QC>)
+            .add_observer(save_recording::<QC>);
+        self.recorders.push(task);
+        self
+    }
+    pub fn broadcast<QC: QueueComponent>(mut self, config: BroadcastConfig<QC>) -> Self {
+        self.bevy_app.insert_resource(config);
+        self
+    }
+    pub fn spectator_delay<QC: QueueComponent>(mut self, delay: core::time::Duration) -> Self {
+        self.bevy_app
+            .insert_resource(SpectatorDelay::<QC>::new(delay));
+        self
+    }
     pub fn run<IP>(self, addr: IP)
     where
         IP: 'static + Send + Sync + tokio::net::ToSocketAddrs + std::fmt::Display,
@@ -103,9 +184,23 @@ impl App {
         let Self {
             axum_router,
             mut bevy_app,
-            ..
+            history,
+            bans,
+            avoid,
+            transports,
+            recorders,
         } = self;
 
+        tokio::spawn(history);
+        tokio::spawn(bans);
+        tokio::spawn(avoid);
+        for transport in transports {
+            tokio::spawn(transport);
+        }
+        for recorder in recorders {
+            tokio::spawn(recorder);
+        }
+
         tokio::spawn(async move {
             let router: Router = axum_router;
             let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
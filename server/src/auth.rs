@@ -1,8 +1,63 @@
-use crate::account::Account;
+use crate::{
+    account::Account,
+    ban::{BAN_REFRESH, Banned, Bans},
+    data::UserData,
+};
 use session::token::ClientToken;
+use sqlx::Pool;
+use tokio::time::Instant;
 
-pub fn auth(token: ClientToken, ip: std::net::SocketAddr) -> Account {
-    match token {
+pub async fn auth<U: UserData>(
+    pool: &Pool<U::DB>,
+    bans: &Bans,
+    token: ClientToken,
+    ip: std::net::SocketAddr,
+) -> eyre::Result<Account> {
+    if let Some(ban) = bans.check_ip(ip.ip()) {
+        return Err(Banned(ban).into());
+    }
+    let account = match token {
         ClientToken::Guest => Account::Guest { ip },
+    };
+    if let Some(ban) = U::account_ban(pool, &account).await?
+        && ban.is_active(std::time::SystemTime::now())
+    {
+        return Err(Banned(ban).into());
+    }
+    Ok(account)
+}
+
+/// The account a connection authenticated as, so its messages skip the ban checks
+/// until the token changes or [`BAN_REFRESH`] passes.
+#[derive(Clone, Copy, Debug)]
+pub struct Authenticated {
+    token: ClientToken,
+    account: Account,
+    checked: Instant,
+}
+
+impl Authenticated {
+    /// Account for `token`, authenticating again only if the cached one is missing or stale
+    pub async fn get<U: UserData>(
+        cached: &mut Option<Self>,
+        pool: &Pool<U::DB>,
+        bans: &Bans,
+        token: ClientToken,
+        ip: std::net::SocketAddr,
+    ) -> eyre::Result<Account> {
+        if let Some(auth) = cached
+            && auth.token == token
+            && auth.checked.elapsed() < BAN_REFRESH
+        {
+            return Ok(auth.account);
+        }
+        *cached = None;
+        let account = auth::<U>(pool, bans, token, ip).await?;
+        *cached = Some(Self {
+            token,
+            account,
+            checked: Instant::now(),
+        });
+        Ok(account)
     }
 }
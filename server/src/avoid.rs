@@ -0,0 +1,99 @@
+use crate::{
+    account::{Account, AccountMap},
+    data::UserData,
+    queries::EntityId,
+};
+use bevy::prelude::*;
+use core::{future::Future, pin::Pin, time::Duration};
+use hashbrown::HashSet;
+use sqlx::Pool;
+
+/// How often the avoid lists of queued players are queried again, so blocks made and
+/// rematch cooldowns that ran out while waiting are honoured.
+pub const AVOID_REFRESH: Duration = Duration::from_secs(30);
+
+/// Accounts a queued player must not be placed in a lobby with, as returned by
+/// [`UserData::avoid_list`] and [`UserData::recent_opponents`]. Both are empty unless
+/// the game implements them.
+#[derive(Clone, Debug, Default, Component)]
+pub struct Avoid {
+    pub blocked: HashSet<Account>,
+    pub recent: HashSet<Account>,
+}
+
+impl Avoid {
+    pub async fn query<U: UserData>(pool: &Pool<U::DB>, account: &Account) -> eyre::Result<Self> {
+        let blocked = U::avoid_list(pool, account).await?.into_iter().collect();
+        let recent = match U::rematch_cooldown() {
+            Some(cooldown) => {
+                let since = std::time::SystemTime::now() - cooldown;
+                U::recent_opponents(pool, account, since)
+                    .await?
+                    .into_iter()
+                    .collect()
+            }
+            None => HashSet::new(),
+        };
+        Ok(Self { blocked, recent })
+    }
+    pub fn allows(&self, account: &Account) -> bool {
+        !self.blocked.contains(account) && !self.recent.contains(account)
+    }
+    /// Both players are willing to be matched with each other
+    pub fn mutual(a: (&Account, &Self), b: (&Account, &Self)) -> bool {
+        a.1.allows(b.0) && b.1.allows(a.0)
+    }
+}
+
+/// Queries avoid lists for queued players off the bevy thread, see [`refresh_avoid`].
+#[derive(Clone, Debug, Resource)]
+pub struct AvoidRefresh {
+    requests: kanal::Sender<Account>,
+    results: kanal::Receiver<(Account, Avoid)>,
+}
+
+impl AvoidRefresh {
+    pub fn new<U: UserData>(pool: Pool<U::DB>) -> (Self, Pin<Box<dyn Future<Output = ()> + Send>>) {
+        let (requests, receiver) = kanal::unbounded();
+        let (sender, results) = kanal::unbounded();
+        let receiver = receiver.to_async();
+        let sender = sender.to_async();
+        let task = Box::pin(async move {
+            while let Ok(account) = receiver.recv().await {
+                match Avoid::query::<U>(&pool, &account).await {
+                    Ok(avoid) => {
+                        if sender.send((account, avoid)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => leptos::logging::log!("error refreshing avoid list: {e:?}"),
+                }
+            }
+        });
+        (Self { requests, results }, task)
+    }
+}
+
+/// Every [`AVOID_REFRESH`], query the avoid lists of players still in a queue again,
+/// and apply the lists that came back to players who haven't left it since.
+pub fn refresh_avoid(
+    time: Res<Time>,
+    mut next: Local<Duration>,
+    refresh: Res<AvoidRefresh>,
+    accounts: Res<AccountMap>,
+    mut queued: Query<(&Account, &mut Avoid), Without<EntityId>>,
+) {
+    while let Ok(Some((account, avoid))) = refresh.results.try_recv() {
+        if let Some(entity) = accounts.get(&account)
+            && let Ok((_, mut current)) = queued.get_mut(entity)
+        {
+            *current = avoid;
+        }
+    }
+    if time.elapsed() >= *next {
+        *next = time.elapsed() + AVOID_REFRESH;
+        for (account, _) in queued.iter() {
+            let _ = refresh.requests.send(*account);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::transport::Outbound;
+use bevy::prelude::Resource;
+use core::time::Duration;
+use std::{
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+/// How much a slow client may fall behind before it is disconnected.
+#[derive(Clone, Copy, Debug)]
+pub struct BackpressurePolicy {
+    /// Queued frames at which a connection counts as saturated
+    pub capacity: usize,
+    /// How long a connection may stay saturated before it is closed
+    pub max_saturation: Duration,
+}
+
+impl Default for BackpressurePolicy {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            max_saturation: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Counters for frames that never reached a client as sent.
+#[derive(Debug, Default)]
+pub struct SendMetrics {
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+    disconnected: AtomicU64,
+}
+
+impl SendMetrics {
+    /// Frames sent to a connection that was already closed
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+    /// State frames replaced by a newer one before being written
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+    /// Connections closed for staying saturated too long
+    pub fn disconnected(&self) -> u64 {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+}
+
+/// Server-wide backpressure policy and metrics, shared by every connection.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct Backpressure {
+    policy: Arc<RwLock<BackpressurePolicy>>,
+    metrics: Arc<SendMetrics>,
+}
+
+impl Backpressure {
+    pub fn policy(&self) -> BackpressurePolicy {
+        *self.policy.read().unwrap()
+    }
+    pub fn set_policy(&self, policy: BackpressurePolicy) {
+        *self.policy.write().unwrap() = policy;
+    }
+    pub fn metrics(&self) -> &SendMetrics {
+        &self.metrics
+    }
+    /// Outgoing queue for a new connection
+    pub fn channel(&self) -> (Outbox, OutboxReceiver) {
+        let (queue, receiver) = kanal::unbounded();
+        let state = Arc::new(Mutex::new(StateSlot::default()));
+        let outbox = Outbox {
+            queue,
+            state: state.clone(),
+            saturated_since: Mutex::new(None),
+            backpressure: self.clone(),
+        };
+        let receiver = OutboxReceiver {
+            queue: receiver.to_async(),
+            state,
+        };
+        (outbox, receiver)
+    }
+}
+
+enum Outgoing {
+    Control(Outbound),
+    /// Placeholder for whatever state message is latest when the writer reaches it,
+    /// skipped unless it is the slot's current placeholder
+    State(u64),
+}
+
+/// Latest unwritten state message, and the placeholder it is delivered at
+#[derive(Default)]
+struct StateSlot {
+    frame: Option<Outbound>,
+    placeholder: u64,
+    /// Control messages were queued after the placeholder, so a newer state message
+    /// delivered there would overtake them
+    behind: bool,
+}
+
+/// Sending half of a connection's outgoing queue.
+/// Control messages are always queued in order, while state messages share a single
+/// slot so a slow client only ever receives the latest one. A state message is never
+/// delivered ahead of control messages queued before it.
+pub struct Outbox {
+    queue: kanal::Sender<Outgoing>,
+    state: Arc<Mutex<StateSlot>>,
+    saturated_since: Mutex<Option<Instant>>,
+    backpressure: Backpressure,
+}
+
+impl core::fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Outbox")
+            .field("queued", &self.queue.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Outbox {
+    pub fn is_closed(&self) -> bool {
+        self.queue.is_closed() || self.queue.is_disconnected()
+    }
+    /// Whether a state frame is still waiting to be written
+    pub fn state_pending(&self) -> bool {
+        self.state.lock().unwrap().frame.is_some()
+    }
+    pub fn control(&self, outbound: Outbound) {
+        // held while queueing, so the writer can't take the state message in between
+        let mut slot = self.state.lock().unwrap();
+        slot.behind |= slot.frame.is_some();
+        self.push(Outgoing::Control(outbound));
+    }
+    pub fn state(&self, outbound: Outbound) {
+        let mut slot = self.state.lock().unwrap();
+        if slot.frame.replace(outbound).is_some() {
+            self.backpressure
+                .metrics
+                .coalesced
+                .fetch_add(1, Ordering::Relaxed);
+            if !slot.behind {
+                return;
+            }
+        }
+        // a fresh placeholder behind everything queued so far, leaving any older one stale
+        slot.placeholder += 1;
+        slot.behind = false;
+        self.push(Outgoing::State(slot.placeholder));
+    }
+    fn push(&self, outgoing: Outgoing) {
+        if self.queue.send(outgoing).is_err() {
+            self.backpressure
+                .metrics
+                .dropped
+                .fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let policy = self.backpressure.policy();
+        let mut since = self.saturated_since.lock().unwrap();
+        if self.queue.len() < policy.capacity {
+            *since = None;
+            return;
+        }
+        let now = Instant::now();
+        match *since {
+            None => *since = Some(now),
+            Some(start) if now.duration_since(start) > policy.max_saturation => {
+                if self.queue.close().is_ok() {
+                    self.backpressure
+                        .metrics
+                        .disconnected
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Receiving half of a connection's outgoing queue, drained by the writer task.
+pub struct OutboxReceiver {
+    queue: kanal::AsyncReceiver<Outgoing>,
+    state: Arc<Mutex<StateSlot>>,
+}
+
+impl OutboxReceiver {
+    /// Next frame to write, or `None` once the connection is closed
+    pub async fn recv(&self) -> Option<Outbound> {
+        loop {
+            match self.queue.recv().await.ok()? {
+                Outgoing::Control(outbound) => return Some(outbound),
+                Outgoing::State(placeholder) => {
+                    let mut slot = self.state.lock().unwrap();
+                    if slot.placeholder == placeholder
+                        && let Some(outbound) = slot.frame.take()
+                    {
+                        return Some(outbound);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(byte: u8) -> Outbound {
+        Outbound::Message(vec![byte])
+    }
+
+    /// Everything queued so far, as the writer would write it
+    fn drain(outbox: Outbox, receiver: OutboxReceiver) -> Vec<u8> {
+        drop(outbox);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut written = Vec::new();
+            while let Some(outbound) = receiver.recv().await {
+                if let Outbound::Message(bytes) = outbound {
+                    written.extend(bytes);
+                }
+            }
+            written
+        })
+    }
+
+    #[test]
+    fn coalesced() {
+        let backpressure = Backpressure::default();
+        let (outbox, receiver) = backpressure.channel();
+        outbox.state(message(1));
+        outbox.state(message(2));
+        outbox.control(message(10));
+        assert_eq!(drain(outbox, receiver), [2, 10]);
+        assert_eq!(backpressure.metrics().coalesced(), 1);
+    }
+
+    #[test]
+    fn ordered() {
+        let backpressure = Backpressure::default();
+        let (outbox, receiver) = backpressure.channel();
+        outbox.control(message(10));
+        outbox.state(message(1));
+        outbox.control(message(11));
+        // written after 11 was queued, so it must not overtake it
+        outbox.state(message(2));
+        outbox.control(message(12));
+        outbox.state(message(3));
+        assert_eq!(drain(outbox, receiver), [10, 11, 12, 3]);
+        assert_eq!(backpressure.metrics().coalesced(), 2);
+    }
+}
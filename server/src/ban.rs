@@ -0,0 +1,217 @@
+use crate::{data::UserData, send::SendFrame};
+use bevy::prelude::Resource;
+use core::{fmt, future::Future, pin::Pin, str::FromStr, time::Duration};
+use sqlx::Pool;
+use std::{
+    net::IpAddr,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+/// How often [`Bans`] reloads IP bans, and how long a connection's account ban check lasts.
+pub const BAN_REFRESH: Duration = Duration::from_secs(30);
+
+pub use session::protocol::BAN_CLOSE_CODE;
+
+/// A ban with its reason and optional expiry.
+#[derive(Clone, Debug)]
+pub struct Ban {
+    pub reason: String,
+    pub expires: Option<SystemTime>,
+}
+
+impl Ban {
+    pub fn is_active(&self, now: SystemTime) -> bool {
+        self.expires.is_none_or(|expires| expires > now)
+    }
+    /// Close the connection, telling the client the ban reason
+    pub fn close(&self, send_frame: &SendFrame) {
+        send_frame.close(BAN_CLOSE_CODE, self.reason.clone());
+    }
+}
+
+/// Error returned by authentication when the account or address is banned.
+#[derive(Clone, Debug)]
+pub struct Banned(pub Ban);
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "banned: {}", self.0.reason)
+    }
+}
+
+impl std::error::Error for Banned {}
+
+/// An IP address range in CIDR notation, e.g. `10.0.0.0/8`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    pub addr: IpAddr,
+    pub prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            (IpAddr::V4(_), IpAddr::V6(ip)) => ip
+                .to_ipv4_mapped()
+                .is_some_and(|ip| self.contains(IpAddr::V4(ip))),
+            (IpAddr::V6(_), IpAddr::V4(ip)) => self.contains(IpAddr::V6(ip.to_ipv6_mapped())),
+        }
+    }
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        let prefix = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, prefix }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((addr, prefix)) = s.split_once('/') else {
+            return Ok(Self::from(s.parse::<IpAddr>()?));
+        };
+        let addr: IpAddr = addr.parse()?;
+        let prefix: u8 = prefix.parse()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            eyre::bail!("CIDR prefix {prefix} exceeds {max}");
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// A ban covering every address in `range`.
+#[derive(Clone, Debug)]
+pub struct IpBan {
+    pub range: Cidr,
+    pub ban: Ban,
+}
+
+/// IP bans held in memory and reloaded every [`BAN_REFRESH`], so connections are
+/// checked without a database round trip.
+#[derive(Clone, Debug, Default, Resource)]
+pub struct Bans(Arc<RwLock<Vec<IpBan>>>);
+
+impl Bans {
+    pub fn new<U: UserData>(pool: Pool<U::DB>) -> (Self, Pin<Box<dyn Future<Output = ()> + Send>>) {
+        let bans = Self::default();
+        let list = bans.clone();
+        let task = Box::pin(async move {
+            loop {
+                match U::ip_bans(&pool).await {
+                    Ok(bans) => list.set(bans),
+                    Err(e) => leptos::logging::log!("error loading IP bans: {e:?}"),
+                }
+                tokio::time::sleep(BAN_REFRESH).await;
+            }
+        });
+        (bans, task)
+    }
+    /// Find an active ban covering `ip`, if any.
+    /// Replace the IP bans, e.g. to apply a new ban before the next refresh
+    pub fn set(&self, bans: Vec<IpBan>) {
+        *self.0.write().unwrap() = bans;
+    }
+    pub fn check_ip(&self, ip: IpAddr) -> Option<Ban> {
+        let now = SystemTime::now();
+        self.0
+            .read()
+            .unwrap()
+            .iter()
+            .find(|IpBan { range, ban }| range.contains(ip) && ban.is_active(now))
+            .map(|IpBan { ban, .. }| ban.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            cidr("10.0.0.0/8"),
+            Cidr {
+                addr: ip("10.0.0.0"),
+                prefix: 8
+            }
+        );
+        assert_eq!(cidr("10.1.2.3").prefix, 32);
+        assert_eq!(cidr("2001:db8::/32").prefix, 32);
+        assert_eq!(cidr("2001:db8::1").prefix, 128);
+        assert_eq!(cidr("::/0").prefix, 0);
+        assert_eq!(cidr("1.2.3.4/32").prefix, 32);
+        assert_eq!(cidr("::1/128").prefix, 128);
+    }
+
+    #[test]
+    fn malformed() {
+        for s in [
+            "",
+            "/8",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "::/129",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+            "10.0.0/8",
+            "example.com/8",
+        ] {
+            assert!(s.parse::<Cidr>().is_err(), "{s:?} parsed");
+        }
+    }
+
+    #[test]
+    fn ipv4() {
+        let net = cidr("192.168.0.0/16");
+        assert!(net.contains(ip("192.168.0.1")));
+        assert!(net.contains(ip("192.168.255.255")));
+        assert!(!net.contains(ip("192.169.0.1")));
+        let single = cidr("1.2.3.4/32");
+        assert!(single.contains(ip("1.2.3.4")));
+        assert!(!single.contains(ip("1.2.3.5")));
+        let all = cidr("0.0.0.0/0");
+        assert!(all.contains(ip("255.255.255.255")));
+        assert!(all.contains(ip("0.0.0.0")));
+    }
+
+    #[test]
+    fn ipv6() {
+        let net = cidr("2001:db8::/32");
+        assert!(net.contains(ip("2001:db8:ffff::1")));
+        assert!(!net.contains(ip("2001:db9::1")));
+        let single = cidr("::1/128");
+        assert!(single.contains(ip("::1")));
+        assert!(!single.contains(ip("::2")));
+        let all = cidr("::/0");
+        assert!(all.contains(ip("ffff::1")));
+    }
+
+    #[test]
+    fn mapped() {
+        // v4 clients reaching a dual stack listener show up as mapped v6 addresses
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+        assert!(cidr("::ffff:10.0.0.0/104").contains(ip("10.1.2.3")));
+        assert!(!cidr("2001:db8::/32").contains(ip("10.1.2.3")));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{matchmaking::Accepted, queries::MembersChanged, queue::*};
+use bevy::prelude::*;
+use core::{marker::PhantomData, time::Duration};
+use session::{action::Action, diff::Diff, info::*};
+
+/// Per-queue settings for sending session state to players.
+#[derive(Debug, Resource)]
+pub struct BroadcastConfig<QC: QueueComponent> {
+    /// Deltas sent between full snapshots, so clients can recover from a lost update
+    pub snapshot_interval: u32,
+    /// Maximum broadcasts per second for a session, unlimited if `None`
+    pub max_rate: Option<u32>,
+    _phantom: PhantomData<QC>,
+}
+
+impl<QC: QueueComponent> BroadcastConfig<QC> {
+    pub fn with_snapshot_interval(mut self, snapshot_interval: u32) -> Self {
+        self.snapshot_interval = snapshot_interval;
+        self
+    }
+    pub fn with_max_rate(mut self, hz: u32) -> Self {
+        self.max_rate = Some(hz);
+        self
+    }
+    fn min_interval(&self) -> Option<Duration> {
+        self.max_rate.map(|hz| Duration::from_secs(1) / hz.max(1))
+    }
+}
+
+impl<QC: QueueComponent> Default for BroadcastConfig<QC> {
+    fn default() -> Self {
+        Self {
+            snapshot_interval: 60,
+            max_rate: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Tracks whether a session has anything new to send, and when it last sent.
+#[derive(Component)]
+pub struct Broadcast<QC: QueueComponent> {
+    dirty: bool,
+    last: Option<Duration>,
+    spectators: u64,
+    _phantom: PhantomData<QC>,
+}
+
+impl<QC: QueueComponent> Default for Broadcast<QC> {
+    fn default() -> Self {
+        Self {
+            dirty: true,
+            last: None,
+            spectators: 0,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<QC: QueueComponent> Broadcast<QC> {
+    /// Whether the session should broadcast now, consuming the pending change if so
+    pub fn ready(&mut self, spectators: u64, now: Duration, config: &BroadcastConfig<QC>) -> bool {
+        self.dirty |= self.spectators != spectators;
+        if !self.dirty {
+            return false;
+        }
+        if let (Some(last), Some(interval)) = (self.last, config.min_interval())
+            && now.saturating_sub(last) < interval
+        {
+            return false;
+        }
+        self.dirty = false;
+        self.last = Some(now);
+        self.spectators = spectators;
+        true
+    }
+}
+
+/// Marks sessions whose shared state, member states, or member connections changed.
+/// Stopwatch ticks bypass change detection, so clients advance running timers locally.
+pub fn mark_changed<QC: QueueComponent>(
+    shared: Query<Entity, (With<Accepted>, Changed<QC::Shared>)>,
+    users: MembersChanged<QC>,
+    mut broadcasts: Query<&mut Broadcast<QC>>,
+) {
+    let sessions = shared.iter().chain(users.iter().map(|id| id.0));
+    for session in sessions {
+        if let Ok(mut broadcast) = broadcasts.get_mut(session) {
+            broadcast.dirty = true;
+        }
+    }
+}
+
+/// The session info last sent to a player, which deltas are computed against.
+#[derive(Component)]
+pub struct LastSent<QC: QueueComponent> {
+    info: Option<Info<QC::User, QC::Shared>>,
+    deltas: u32,
+}
+
+impl<QC: QueueComponent> Default for LastSent<QC> {
+    fn default() -> Self {
+        Self {
+            info: None,
+            deltas: 0,
+        }
+    }
+}
+
+impl<QC: QueueComponent> LastSent<QC> {
+    /// Forget what the client has, so the next update is a full snapshot
+    pub fn reset(&mut self) {
+        self.info = None;
+    }
+    /// Whether the client has been sent any info yet
+    pub fn has_sent(&self) -> bool {
+        self.info.is_some()
+    }
+    /// The update bringing the client from the last sent info to `info`, if anything changed.
+    /// With `resync` a snapshot is sent instead, for when the previous update may be replaced.
+    pub fn next(
+        &mut self,
+        info: Info<QC::User, QC::Shared>,
+        config: &BroadcastConfig<QC>,
+        resync: bool,
+    ) -> Option<StateInfo<QC::Action>>
+    where
+        QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+    {
+        let update = match &self.info {
+            Some(last) if !resync && self.deltas < config.snapshot_interval => {
+                let delta = info.diff(last)?;
+                self.deltas += 1;
+                StateInfo::SessionDelta(delta)
+            }
+            _ => {
+                self.deltas = 0;
+                StateInfo::Session(info.clone())
+            }
+        };
+        self.info = Some(info);
+        Some(update)
+    }
+}
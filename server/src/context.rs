@@ -0,0 +1,119 @@
+use crate::{
+    account::AccountMap,
+    fair::Committed,
+    queries::*,
+    queue::*,
+    send::SendFrame,
+    spectate::{Spectators, spectator_count},
+    update::ActionState,
+};
+use bevy::prelude::*;
+use hashbrown::HashMap;
+use session::{
+    action::Action,
+    context::{Emitted, SessionRng},
+    info::StateInfo,
+    protocol::Events,
+};
+
+/// Random numbers handed to actions through their [`session::Context`].
+#[derive(Clone, Debug, Component)]
+pub struct SessionRandom(pub SessionRng);
+
+/// Events emitted by a session's actions, delivered at the end of the tick.
+#[derive(Component)]
+pub struct SessionEvents<QC: QueueComponent>(pub Vec<Emitted<<QC::Action as Action>::Event>>);
+
+impl<QC: QueueComponent> Default for SessionEvents<QC> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+/// Whether an action or update ended the session, see [`session::Context::end`].
+#[derive(Clone, Copy, Debug, Default, Component)]
+pub struct SessionEnd(pub bool);
+
+/// Send each seat the events emitted for it since the last tick
+pub fn send_events<QC: QueueComponent>(
+    mut sessions: Query<(&QC::Lobby, &mut SessionEvents<QC>)>,
+    send_frames: Query<&SendFrame>,
+) {
+    for (lobby, mut events) in sessions.iter_mut() {
+        if events.0.is_empty() {
+            continue;
+        }
+        let mut batches: HashMap<Entity, Vec<_>> = HashMap::new();
+        for Emitted { to, event } in events.0.drain(..) {
+            match to {
+                Some(index) => {
+                    // only seats of this session, whatever index the game passed
+                    if let Some(user) = lobby.entities().find(|e| e.to_index() == index) {
+                        batches.entry(user).or_default().push(event);
+                    }
+                }
+                None => {
+                    for user in lobby.entities() {
+                        batches.entry(user).or_default().push(event.clone());
+                    }
+                }
+            }
+        }
+        for (user, batch) in batches {
+            if let Ok(send_frame) = send_frames.get(user) {
+                send_frame.send(&Events::<QC::Action>(batch));
+            }
+        }
+    }
+}
+
+/// Send every seat and spectator of an ended session its final state, with the
+/// seed if it was provably fair, then remove the session and everyone in it
+pub fn end_sessions<QC: QueueComponent>(
+    mut commands: Commands,
+    accounts: ResMut<AccountMap>,
+    sessions: Sessions<QC>,
+    users: InSession<QC>,
+    spectators: Spectators,
+    committed: Query<&Committed>,
+) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+{
+    let accounts = &mut accounts.into_inner().0;
+    for session in sessions.iter().filter(|s| s.end.0) {
+        let committed = committed.get(session.entity).ok();
+        let reveal = committed.map(|c| c.reveal.clone());
+        let watching = spectator_count(&spectators, session.entity);
+        let ended = |user: Entity| {
+            let mut info = ActionState::info(session.entity, user, &sessions, &users)?;
+            info.session = session.id.0;
+            info.spectators = watching;
+            info.commitment = committed.map(|c| c.commitment);
+            Some(info)
+        };
+        for entity in session.lobby.entities() {
+            if let Ok(user) = users.get(entity) {
+                if let Some(mut info) = ended(entity) {
+                    info.ack = user.ack.0;
+                    let reveal = reveal.clone();
+                    user.send_frame
+                        .send_state(&StateInfo::<QC::Action>::Ended { info, reveal });
+                }
+                accounts.remove(user.account);
+            }
+            commands.entity(entity).despawn();
+        }
+        let info = ended(Entity::PLACEHOLDER);
+        for (entity, _, send_frame) in spectators
+            .iter()
+            .filter(|(_, s, _)| s.session == session.entity)
+        {
+            if let Some(info) = info.clone() {
+                let reveal = reveal.clone();
+                send_frame.send_state(&StateInfo::<QC::Action>::Ended { info, reveal });
+            }
+            commands.entity(entity).despawn();
+        }
+        commands.entity(session.entity).despawn();
+    }
+}
@@ -1,9 +1,17 @@
-use crate::account::Account;
+use crate::{
+    account::Account,
+    ban::{Ban, IpBan},
+    history::{MatchRecord, SessionRecord},
+};
 use bevy::ecs::component::*;
-use core::future::Future;
+use core::{future::Future, time::Duration};
 use sqlx::*;
 
 /// Data associated with a user account.
+///
+/// The crate is generic over the database and ships no schema, so blocking, rematch
+/// cooldowns, match and session history and bans only work once the game implements
+/// the hooks below against its own tables. Their defaults store nothing and find nothing.
 #[trait_variant::make(Send)]
 pub trait UserData:
     Component<Mutability = Mutable>
@@ -22,4 +30,56 @@ pub trait UserData:
     ) -> impl Future<Output = eyre::Result<Self>> + Send;
     fn matchmake_priority(&self) -> Self::O;
     fn matchmake_valid(&self, user_data: &Self) -> bool;
+    /// Name shown to other lobby members
+    fn display_name(&self) -> String {
+        String::from("Guest")
+    }
+    /// Accounts this account has chosen never to be matched with.
+    /// Nobody is blocked unless implemented.
+    fn avoid_list(
+        _pool: &Pool<Self::DB>,
+        _account: &Account,
+    ) -> impl Future<Output = eyre::Result<Vec<Account>>> + Send {
+        async { Ok(Vec::new()) }
+    }
+    /// Accounts this account has played against since `since`, for [`Self::rematch_cooldown`].
+    /// Must read what [`Self::record_match`] stores, or the cooldown never applies.
+    fn recent_opponents(
+        _pool: &Pool<Self::DB>,
+        _account: &Account,
+        _since: std::time::SystemTime,
+    ) -> impl Future<Output = eyre::Result<Vec<Account>>> + Send {
+        async { Ok(Vec::new()) }
+    }
+    /// Persist a newly formed lobby so it can feed `recent_opponents`.
+    /// Discarded unless implemented.
+    fn record_match(
+        _pool: &Pool<Self::DB>,
+        _record: MatchRecord,
+    ) -> impl Future<Output = eyre::Result<()>> + Send {
+        async { Ok(()) }
+    }
+    /// Persist how a session was initialized as it starts, so it can be reproduced.
+    /// Discarded unless implemented.
+    fn record_session(
+        _pool: &Pool<Self::DB>,
+        _record: SessionRecord,
+    ) -> impl Future<Output = eyre::Result<()>> + Send {
+        async { Ok(()) }
+    }
+    /// The most recent ban placed on this account, expired or not.
+    fn account_ban(
+        _pool: &Pool<Self::DB>,
+        _account: &Account,
+    ) -> impl Future<Output = eyre::Result<Option<Ban>>> + Send {
+        async { Ok(None) }
+    }
+    /// Every IP or CIDR range ban, expired or not.
+    fn ip_bans(_pool: &Pool<Self::DB>) -> impl Future<Output = eyre::Result<Vec<IpBan>>> + Send {
+        async { Ok(Vec::new()) }
+    }
+    /// How long two players are kept apart after being matched, if at all.
+    fn rematch_cooldown() -> Option<Duration> {
+        None
+    }
 }
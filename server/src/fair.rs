@@ -0,0 +1,40 @@
+use crate::queue::*;
+use bevy::prelude::*;
+use core::marker::PhantomData;
+use session::fair::{Entropy, Reveal, commit, mix};
+
+/// Makes sessions of a queue provably fair: the server's seed is committed to by hash
+/// before players accept, mixed with any entropy they accept with, and revealed when
+/// the session ends so clients can recompute every random outcome.
+#[derive(Debug, Resource)]
+pub struct ProvablyFair<QC: QueueComponent>(PhantomData<QC>);
+
+impl<QC: QueueComponent> Default for ProvablyFair<QC> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+/// Entropy a lobby member accepted with.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct Contribution(pub Entropy);
+
+/// Commitment of a provably fair session and what it reveals when the session ends.
+#[derive(Clone, Debug, Component)]
+pub struct Committed {
+    pub commitment: [u8; 32],
+    pub reveal: Reveal,
+}
+
+impl Committed {
+    pub fn new(seed: [u8; 32], entropy: Vec<Entropy>) -> Self {
+        Self {
+            commitment: commit(&seed),
+            reveal: Reveal { seed, entropy },
+        }
+    }
+    /// Seed the session is initialized from
+    pub fn session_seed(&self) -> [u8; 32] {
+        mix(self.reveal.seed, &self.reveal.entropy)
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{account::Account, data::UserData, matchmaking::SessionId};
+use bevy::prelude::*;
+use core::{future::Future, pin::Pin};
+use sqlx::Pool;
+
+/// The players of a session, recorded once everyone accepted and it started.
+#[derive(Clone, Debug)]
+pub struct MatchRecord {
+    pub accounts: Vec<Account>,
+    pub time: std::time::SystemTime,
+}
+
+/// A session that has started, with everything it was initialized from.
+/// Together with the session's actions this is enough to reproduce it.
+#[derive(Clone, Debug)]
+pub struct SessionRecord {
+    pub session: SessionId,
+    /// Seed the session's randomness is drawn from
+    pub seed: [u8; 32],
+    /// The session's settings, bitcode encoded
+    pub settings: Vec<u8>,
+    /// Seat index and account of each player, in lobby order
+    pub seats: Vec<(u64, Account)>,
+    pub time: std::time::SystemTime,
+}
+
+#[derive(Clone, Debug)]
+enum Record {
+    Match(MatchRecord),
+    Session(SessionRecord),
+}
+
+/// Forwards match and session records from bevy systems to the database, through
+/// [`UserData::record_match`] and [`UserData::record_session`], which the game has to
+/// implement for anything to be stored.
+#[derive(Clone, Debug, Resource)]
+pub struct MatchHistory(kanal::Sender<Record>);
+
+impl MatchHistory {
+    pub fn new<U: UserData>(pool: Pool<U::DB>) -> (Self, Pin<Box<dyn Future<Output = ()> + Send>>) {
+        let (sender, receiver) = kanal::unbounded();
+        let receiver = receiver.to_async();
+        let task = Box::pin(async move {
+            while let Ok(record) = receiver.recv().await {
+                let res = match record {
+                    Record::Match(record) => U::record_match(&pool, record).await,
+                    Record::Session(record) => U::record_session(&pool, record).await,
+                };
+                if let Err(e) = res {
+                    leptos::logging::log!("error recording match: {e:?}");
+                }
+            }
+        });
+        (Self(sender), task)
+    }
+    pub fn record(&self, accounts: Vec<Account>) {
+        let time = std::time::SystemTime::now();
+        if let Err(e) = self.0.send(Record::Match(MatchRecord { accounts, time })) {
+            leptos::logging::log!("error sending match record: {e:?}");
+        }
+    }
+    pub fn record_session<S: serde::Serialize>(
+        &self,
+        session: SessionId,
+        seed: [u8; 32],
+        settings: &S,
+        seats: Vec<(u64, Account)>,
+    ) {
+        let settings = match bitcode::serialize(settings) {
+            Ok(settings) => settings,
+            Err(e) => {
+                leptos::logging::log!("error encoding session settings: {e:?}");
+                return;
+            }
+        };
+        let time = std::time::SystemTime::now();
+        let record = Record::Session(SessionRecord {
+            session,
+            seed,
+            settings,
+            seats,
+            time,
+        });
+        if let Err(e) = self.0.send(record) {
+            leptos::logging::log!("error sending session record: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    queue::AsIndex,
+    replay::{Playback, verify},
+    time::AsStopwatch,
+};
+use bevy::prelude::Entity;
+use session::{
+    action::Action,
+    info::Info,
+    queue::AsQueue,
+    replay::{Checksum, Replay, checksum},
+    state::*,
+};
+use std::path::Path;
+
+const USAGE: &str = "usage:
+    list <replay>...             summarize each replay and check it still reproduces
+    timeline <replay>            every action with its tick, seat and result
+    info <replay> <tick> [seat]  info a seat, or a spectator, saw after <tick>
+    diff <replay> <replay>       find where two replays diverge";
+
+/// Command line tool for replay files, run from a game's own binary so the game's
+/// types are linked in:
+///
+/// ```ignore
+/// fn main() -> eyre::Result<()> {
+///     ilium::server::inspect::run::<Game>(std::env::args().skip(1))
+/// }
+/// ```
+pub fn run<Q: AsQueue>(args: impl IntoIterator<Item = String>) -> eyre::Result<()>
+where
+    <Q::Action as Action>::User: UserState<Shared = <Q::Action as Action>::Shared> + Checksum,
+    <Q::Action as Action>::Shared: AsStopwatch + Checksum,
+{
+    let args: Vec<String> = args.into_iter().collect();
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["list", paths @ ..] if !paths.is_empty() => {
+            for path in paths {
+                list::<Q::Action>(path)?;
+            }
+            Ok(())
+        }
+        ["timeline", path] => timeline::<Q::Action>(path),
+        ["info", path, tick] => info::<Q::Action>(path, tick.parse()?, None),
+        ["info", path, tick, seat] => info::<Q::Action>(path, tick.parse()?, Some(seat.parse()?)),
+        ["diff", a, b] => diff::<Q::Action>(a, b),
+        _ => Err(eyre::eyre!("{USAGE}")),
+    }
+}
+
+fn load<A: Action>(path: impl AsRef<Path>) -> eyre::Result<Replay<A>> {
+    let path = path.as_ref();
+    let bytes = std::fs::read(path).map_err(|e| eyre::eyre!("reading {path:?}: {e}"))?;
+    Replay::decode(&bytes).map_err(|e| eyre::eyre!("{path:?}: {e}"))
+}
+
+fn list<A: Action>(path: &str) -> eyre::Result<()>
+where
+    A::User: UserState<Shared = A::Shared> + Checksum,
+    A::Shared: AsStopwatch + Checksum,
+{
+    let replay = load::<A>(path)?;
+    let session = Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let duration = replay.duration();
+    let status = match verify(&replay) {
+        Ok(_) => "reproduces".to_string(),
+        Err(e) => e.to_string(),
+    };
+    println!(
+        "{session}: seats {:?}, {} ticks ({duration:.1?}), {} actions, {status}",
+        replay.seats,
+        replay.ticks.len(),
+        replay.actions.len(),
+    );
+    Ok(())
+}
+
+fn timeline<A: Action>(path: &str) -> eyre::Result<()>
+where
+    A::User: UserState<Shared = A::Shared> + Checksum,
+    A::Shared: AsStopwatch + Checksum,
+{
+    let replay = load::<A>(path)?;
+    let mut playback = Playback::new(&replay);
+    while let Some(resolved) = playback.step() {
+        for (recorded, result) in resolved {
+            let elapsed = playback.elapsed();
+            let result = match result {
+                Ok(()) => "ok".to_string(),
+                Err(e) => format!("rejected: {e:?}"),
+            };
+            println!(
+                "tick {:>6} {elapsed:>10.3?} seat {}: {:?} {result}",
+                recorded.tick, recorded.index, recorded.action
+            );
+        }
+    }
+    Ok(())
+}
+
+fn info<A: Action>(path: &str, tick: usize, seat: Option<u64>) -> eyre::Result<()>
+where
+    A::User: UserState<Shared = A::Shared> + Checksum,
+    A::Shared: AsStopwatch + Checksum,
+{
+    let replay = load::<A>(path)?;
+    if let Some(seat) = seat
+        && !replay.seats.contains(&seat)
+    {
+        return Err(eyre::eyre!("no seat {seat}, seats are {:?}", replay.seats));
+    }
+    let mut playback = Playback::new(&replay);
+    while playback.tick() <= tick {
+        if playback.step().is_none() {
+            return Err(eyre::eyre!(
+                "no tick {tick}, the replay has {}",
+                replay.ticks.len()
+            ));
+        }
+    }
+    // spectators hold no seat, so hidden fields are computed as for the server's placeholder
+    let index = seat.unwrap_or(Entity::PLACEHOLDER.to_index());
+    let state = playback.state();
+    let info: Info<A::User, A::Shared> = Info::new(
+        A::User::info(index, state),
+        A::Shared::info(index, state),
+        index,
+    );
+    println!("{info:#?}");
+    Ok(())
+}
+
+fn diff<A: Action>(a: &str, b: &str) -> eyre::Result<()>
+where
+    A::User: UserState<Shared = A::Shared> + Checksum,
+    A::Shared: AsStopwatch + Checksum,
+{
+    let (a, b) = (load::<A>(a)?, load::<A>(b)?);
+    if a.seed != b.seed {
+        println!("seeds differ");
+    }
+    if a.settings != b.settings {
+        println!("settings differ: {:?} and {:?}", a.settings, b.settings);
+    }
+    if a.seats != b.seats {
+        println!("seats differ: {:?} and {:?}", a.seats, b.seats);
+    }
+    let recorded = |r: &Replay<A>, i: usize| {
+        r.actions
+            .get(i)
+            .map(|a| format!("tick {} seat {}: {:?}", a.tick, a.index, a.action))
+    };
+    if let Some(i) =
+        (0..a.actions.len().max(b.actions.len())).find(|i| recorded(&a, *i) != recorded(&b, *i))
+    {
+        println!(
+            "actions diverge at #{i}: {} and {}",
+            recorded(&a, i).unwrap_or("nothing".into()),
+            recorded(&b, i).unwrap_or("nothing".into()),
+        );
+    }
+    let (mut pa, mut pb) = (Playback::new(&a), Playback::new(&b));
+    loop {
+        let tick = pa.tick();
+        match (pa.step(), pb.step()) {
+            (Some(_), Some(_)) => {
+                if checksum(pa.state()) != checksum(pb.state()) {
+                    println!("states diverge after tick {tick}");
+                    return Ok(());
+                }
+            }
+            (None, None) => break,
+            _ => {
+                println!("one replay ends at tick {tick}, the other runs on");
+                return Ok(());
+            }
+        }
+    }
+    println!("states match through all {} ticks", pa.tick());
+    Ok(())
+}
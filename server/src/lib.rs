@@ -1,13 +1,27 @@
 pub mod account;
 pub mod app;
 pub mod auth;
+pub mod avoid;
+pub mod backpressure;
+pub mod ban;
+pub mod broadcast;
+pub mod context;
 pub mod data;
+pub mod fair;
+pub mod history;
+pub mod inspect;
 pub mod matchmaking;
+pub mod memory;
+pub mod private;
 pub mod queries;
 pub mod queue;
+pub mod replay;
 pub mod send;
+pub mod spectate;
 pub mod state;
+pub mod tcp;
 pub mod time;
+pub mod transport;
 pub mod update;
 pub mod ws;
 
@@ -1,25 +1,59 @@
 use crate::{
-    account::AccountMap,
+    account::{Account, AccountMap},
+    avoid::Avoid,
+    broadcast::{Broadcast, LastSent},
+    context::{SessionEnd, SessionEvents, SessionRandom},
     data::UserData,
+    fair::{Committed, Contribution, ProvablyFair},
+    history::MatchHistory,
+    private::PrivateLobby,
     queries::*,
     queue::*,
-    send::{QueueSignal, Receiver, ReconnectSignal},
-    update::ActionStateInfo,
+    replay::{Recording, ReplayRecorder},
+    send::{QueueSignal, Receiver, ReconnectSignal, SendFrame},
+    spectate::SpectatorFeed,
+    update::Ack,
 };
 use bevy::prelude::*;
 use hashbrown::HashSet;
-use rand::{TryRngCore, rngs::OsRng};
-use session::{action::*, info::StateInfo, state::*};
+use rand::{SeedableRng, TryRngCore, rngs::OsRng};
+use session::{
+    action::*, context::SessionRng, fair::commit, info::StateInfo, replay::Replay, settings::*,
+    state::*,
+};
 
 #[derive(Component)]
 pub struct Accepted;
 
+/// Seed a session's randomness is drawn from: its shared state's `init`, its
+/// [`SessionRandom`] and any `#[ilium(rng)]` field. In a [`ProvablyFair`] queue it is
+/// first mixed with the players' entropy. Kept for the session's lifetime.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct Seed(pub [u8; 32]);
+
+/// Id a session is recorded and spectated under, which unlike its entity is never
+/// reused, see [`crate::history::SessionRecord`] and [`session::info::Info::session`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Component)]
+pub struct SessionId(pub u64);
+
+/// A lobby member's pick for the session settings.
+#[derive(Component)]
+pub struct LobbyVote<QC: QueueComponent>(pub Option<<QC::Shared as SharedState>::Settings>);
+
+impl<QC: QueueComponent> Default for LobbyVote<QC> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
 pub fn process_queue<QC: QueueComponent, U: UserData>(
     mut commands: Commands,
     receiver: ResMut<Receiver<QueueSignal<QC, U>>>,
     accounts: ResMut<AccountMap>,
     in_queue: InQueue<QC, U>,
     in_lobby: InLobby<QC>,
+    in_private: InPrivateLobby<QC>,
+    mut votes: Query<&mut LobbyVote<QC>>,
 ) where
     QC::Action: Action<Shared = QC::Shared, User = QC::User>,
 {
@@ -31,27 +65,45 @@ pub fn process_queue<QC: QueueComponent, U: UserData>(
                 send_frame,
                 ping,
                 user_data,
+                avoid,
                 account,
                 ..
             } => {
                 if !accounts.contains_key(&account) {
-                    send_frame.send(&StateInfo::Queue::<ActionStateInfo<QC>>);
-                    let mut ec = commands.spawn((account, ping, user_data, send_frame));
+                    send_frame.send(&StateInfo::<QC::Action>::Queue);
+                    let mut ec = commands.spawn((account, ping, user_data, avoid, send_frame));
                     ec.insert(QC::default());
                     let entity = ec.id();
                     accounts.insert(account, entity);
                 }
             }
-            QueueSignal::Accept { account, .. } => {
+            QueueSignal::Accept {
+                account, entropy, ..
+            } => {
                 if let Some(player) = accounts.get(&account).and_then(|e| in_lobby.get(*e).ok())
                     && let Ok(mut ec) = commands.get_entity(player.entity)
                 {
                     ec.insert(Accepted);
+                    if let Some(entropy) = entropy {
+                        ec.insert(Contribution(entropy));
+                    }
+                }
+            }
+            QueueSignal::Vote {
+                account, settings, ..
+            } => {
+                if let Some(entity) = accounts.get(&account)
+                    && (in_lobby.contains(*entity) || in_private.contains(*entity))
+                    && let Ok(mut vote) = votes.get_mut(*entity)
+                {
+                    vote.0 = Some(settings);
                 }
             }
             QueueSignal::Leave { account, .. } => {
                 if let Some(entity) = accounts.get(&account)
-                    && (in_queue.contains(*entity) || in_lobby.contains(*entity))
+                    && (in_queue.contains(*entity)
+                        || in_lobby.contains(*entity)
+                        || in_private.contains(*entity))
                     && let Ok(mut ec) = commands.get_entity(*entity)
                 {
                     ec.despawn();
@@ -66,6 +118,7 @@ pub fn reconnect<QC: QueueComponent>(
     receiver: ResMut<Receiver<ReconnectSignal<QC>>>,
     accounts: ResMut<AccountMap>,
     mut in_session: InSession<QC>,
+    mut last_sent: Query<&mut LastSent<QC>>,
 ) {
     let accounts = &mut accounts.into_inner().0;
     let receiver = &receiver;
@@ -81,6 +134,11 @@ pub fn reconnect<QC: QueueComponent>(
         {
             *ec.send_frame = send_frame;
             *ec.ping = ping;
+            // a new connection numbers its actions from 1 again
+            *ec.ack = Ack::default();
+            if let Ok(mut last_sent) = last_sent.get_mut(entity) {
+                last_sent.reset();
+            }
         }
     }
 }
@@ -92,55 +150,216 @@ where
 {
     let mut users: Vec<_> = in_queue
         .iter()
-        .map(|user| (user.entity, user.user_data.clone()))
+        .map(|user| {
+            (
+                user.entity,
+                user.user_data.clone(),
+                *user.account,
+                user.avoid,
+            )
+        })
         .collect();
-    users.sort_unstable_by_key(|(_, u)| u.matchmake_priority());
+    users.sort_unstable_by_key(|(_, u, ..)| u.matchmake_priority());
     let mut taken = HashSet::new();
-    for (_entity, user) in users.iter() {
-        let valid: Vec<_> = users
-            .iter()
-            .filter_map(|(e, u)| (!taken.contains(e) && user.matchmake_valid(u)).then_some(*e))
-            .collect();
+    for (entity, user, account, avoid) in users.iter() {
+        if taken.contains(entity) {
+            continue;
+        }
+        // the lobby is built around this user, so they are always part of it
+        let mut valid: Vec<(Entity, &Account, &Avoid)> = vec![(*entity, account, avoid)];
+        for (e, u, account, avoid) in users.iter() {
+            if valid.len() < QC::Lobby::capacity()
+                && e != entity
+                && !taken.contains(e)
+                && user.matchmake_valid(u)
+                && valid
+                    .iter()
+                    .all(|(_, a, b)| Avoid::mutual((account, avoid), (a, b)))
+            {
+                valid.push((*e, account, avoid));
+            }
+        }
+        let valid: Vec<_> = valid.into_iter().map(|(e, ..)| e).collect();
         let lobby = QC::Lobby::try_from(&valid).ok();
         if let Some(lobby) = lobby {
             valid.into_iter().for_each(|e| {
                 taken.insert(e);
             });
-            let mut seed = [0u8; 32];
-            OsRng.try_fill_bytes(&mut seed).expect("OSRng Error");
-            let shared_state = <QC::Shared as SharedState>::init(seed);
-            let session_id = EntityId(commands.spawn((shared_state, lobby.clone())).id());
+            let mut ec = commands.spawn_empty();
+            new_session::<QC>(&mut ec, lobby.clone());
+            let session_id = EntityId(ec.id());
             for entity in lobby.entities() {
-                if let Ok(user) = in_queue.get(entity) {
-                    commands.entity(entity).insert(session_id);
-                    user.send_frame
-                        .send(&StateInfo::<ActionStateInfo<QC>>::Lobby);
-                }
+                commands
+                    .entity(entity)
+                    .insert((session_id, LobbyVote::<QC>::default()));
+            }
+        }
+    }
+}
+
+/// Turn an entity into a pending session for `lobby`, awaiting `init_session`
+pub fn new_session<QC: QueueComponent>(ec: &mut EntityCommands, lobby: QC::Lobby) {
+    let mut seed = [0u8; 32];
+    OsRng.try_fill_bytes(&mut seed).expect("OSRng Error");
+    let id = OsRng.try_next_u64().expect("OSRng Error");
+    ec.insert((Seed(seed), SessionId(id), lobby));
+}
+
+/// Lobby info for the members of `lobby`, with `index` left for the recipient
+pub fn lobby_info<QC: QueueComponent, U: UserData>(
+    lobby: &[Entity],
+    members: &Query<LobbyMemberQuery<QC, U>>,
+    commitment: Option<[u8; 32]>,
+) -> LobbyInfo<<QC::Shared as SharedState>::Settings> {
+    let votes: Vec<_> = lobby
+        .iter()
+        .map(|e| members.get(*e).ok().and_then(|m| m.vote.0.clone()))
+        .collect();
+    let members = lobby
+        .iter()
+        .filter_map(|e| {
+            let member = members.get(*e).ok()?;
+            Some(LobbyMember {
+                index: e.to_index(),
+                name: member.user_data.display_name(),
+                accepted: member.accepted,
+                vote: member.vote.0.clone(),
+            })
+        })
+        .collect();
+    LobbyInfo {
+        members,
+        settings: <QC::Shared as SharedState>::Settings::tally(&votes),
+        index: 0,
+        commitment,
+    }
+}
+
+/// Send lobby info to every member of a pending session whenever someone accepts or votes.
+/// If a member leaves before the session starts, the lobby is dissolved and the others
+/// go back to the queue, or to their private lobby if it was one.
+pub fn update_lobby<QC: QueueComponent, U: UserData>(
+    mut commands: Commands,
+    sessions: SessionsPending<QC>,
+    private: Query<(), With<PrivateLobby>>,
+    changed: LobbyChanged<QC>,
+    members: Query<LobbyMemberQuery<QC, U>>,
+    frames: Query<&SendFrame>,
+    fair: Option<Res<ProvablyFair<QC>>>,
+) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+{
+    for session in sessions.iter() {
+        if session.lobby.entities().all(|e| members.contains(e)) {
+            continue;
+        }
+        if private.contains(session.entity) {
+            // members still point at the lobby, which drops whoever left once it is back
+            for e in session.lobby.entities().filter(|e| members.contains(*e)) {
+                commands.entity(e).remove::<(Accepted, Contribution)>();
+            }
+            commands
+                .entity(session.entity)
+                .remove::<(QC::Lobby, Seed, SessionId)>()
+                .insert(QC::default());
+            continue;
+        }
+        for e in session.lobby.entities().filter(|e| members.contains(*e)) {
+            commands
+                .entity(e)
+                .remove::<(EntityId, LobbyVote<QC>, Accepted, Contribution)>();
+            if let Ok(send_frame) = frames.get(e) {
+                send_frame.send(&StateInfo::<QC::Action>::Queue);
+            }
+        }
+        commands.entity(session.entity).despawn();
+    }
+    let dirty: HashSet<Entity> = changed.iter().map(|id| id.0).collect();
+    for session in dirty.into_iter().filter_map(|e| sessions.get(e).ok()) {
+        if !session.lobby.entities().all(|e| members.contains(e)) {
+            continue;
+        }
+        let commitment = fair.is_some().then(|| commit(&session.seed.0));
+        let lobby: Vec<_> = session.lobby.entities().collect();
+        let mut info = lobby_info(&lobby, &members, commitment);
+        for e in lobby {
+            if let Ok(send_frame) = frames.get(e) {
+                info.index = e.to_index();
+                send_frame.send(&StateInfo::<QC::Action>::Lobby(info.clone()));
             }
         }
     }
 }
 
 ///Initialize a new session from accepted lobbies
+#[allow(clippy::too_many_arguments)]
 pub fn init_session<QC: QueueComponent>(
     mut commands: Commands,
     accepted: InLobbyAccepted<QC>,
-    mut sessions: SessionsPending<QC>,
+    votes: Query<&LobbyVote<QC>>,
+    contributions: Query<&Contribution>,
+    sessions: SessionsPending<QC>,
+    history: Res<MatchHistory>,
+    fair: Option<Res<ProvablyFair<QC>>>,
+    recorder: Option<Res<ReplayRecorder<QC>>>,
 ) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
     QC::User: UserState<Shared = QC::Shared>,
 {
-    for mut session in sessions.iter_mut() {
+    for session in sessions.iter() {
         if session.lobby.entities().all(|e| accepted.contains(e)) {
-            let user_states =
-                <QC::User as UserState>::init(session.state.as_mut(), session.lobby.len());
+            let votes: Vec<_> = session
+                .lobby
+                .entities()
+                .map(|e| votes.get(e).ok().and_then(|vote| vote.0.clone()))
+                .collect();
+            let settings = <QC::Shared as SharedState>::Settings::tally(&votes);
+            let mut seed = session.seed.0;
+            if fair.is_some() {
+                let entropy = session
+                    .lobby
+                    .entities()
+                    .filter_map(|e| contributions.get(e).ok().map(|c| c.0))
+                    .collect();
+                let committed = Committed::new(seed, entropy);
+                seed = committed.session_seed();
+                commands.entity(session.entity).insert(committed);
+            }
+            let seats: Vec<_> = session
+                .lobby
+                .entities()
+                .filter_map(|e| Some((e.to_index(), *accepted.get(e).ok()?.account)))
+                .collect();
+            history.record(seats.iter().map(|(_, account)| *account).collect());
+            history.record_session(*session.id, seed, &settings, seats);
+            if recorder.is_some() {
+                let seats = session.lobby.entities().map(|e| e.to_index()).collect();
+                let replay = Replay::new(seed, settings.clone(), seats);
+                commands
+                    .entity(session.entity)
+                    .insert(Recording::<QC>(replay));
+            }
+            let mut shared_state = <QC::Shared as SharedState>::init(seed, settings);
+            let user_states = <QC::User as UserState>::init(&mut shared_state, session.lobby.len());
             session
                 .lobby
                 .entities()
-                .zip(user_states.into_iter())
+                .zip(user_states)
                 .for_each(|(e, state)| {
-                    commands.entity(e).insert(state);
+                    commands
+                        .entity(e)
+                        .insert((state, LastSent::<QC>::default(), Ack::default()))
+                        .remove::<(LobbyVote<QC>, Contribution)>();
                 });
-            commands.entity(session.entity).insert(Accepted);
+            commands.entity(session.entity).insert((
+                shared_state,
+                Accepted,
+                Broadcast::<QC>::default(),
+                SpectatorFeed::<QC>::default(),
+                SessionRandom(SessionRng::from_seed(seed)),
+                SessionEvents::<QC>::default(),
+                SessionEnd::default(),
+            ));
         }
     }
 }
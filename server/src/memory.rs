@@ -0,0 +1,131 @@
+use crate::transport::{Connection, ConnectionRead, ConnectionWrite, Inbound, Outbound, Transport};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::{
+        Arc,
+        atomic::{AtomicU16, Ordering},
+    },
+};
+
+/// In-process transport for tests and local bots.
+pub struct MemoryTransport(kanal::AsyncReceiver<MemoryConnection>);
+
+/// Opens connections to a [`MemoryTransport`].
+#[derive(Clone, Debug)]
+pub struct MemoryConnector {
+    incoming: kanal::AsyncSender<MemoryConnection>,
+    port: Arc<AtomicU16>,
+}
+
+pub fn memory() -> (MemoryTransport, MemoryConnector) {
+    let (incoming, receiver) = kanal::unbounded_async();
+    let connector = MemoryConnector {
+        incoming,
+        port: Arc::new(AtomicU16::new(1)),
+    };
+    (MemoryTransport(receiver), connector)
+}
+
+impl MemoryConnector {
+    pub async fn connect(&self) -> eyre::Result<MemoryClient> {
+        let (send_inbound, recv_inbound) = kanal::unbounded_async();
+        let (send_outbound, recv_outbound) = kanal::unbounded_async();
+        let (send_pong, recv_pong) = kanal::unbounded_async();
+        // each client gets its own loopback port so logs can tell them apart
+        let port = self.port.fetch_add(1, Ordering::Relaxed);
+        let connection = MemoryConnection {
+            addr: SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            read: MemoryRead {
+                inbound: recv_inbound,
+                pongs: recv_pong,
+            },
+            write: MemoryWrite {
+                outbound: send_outbound,
+                pongs: send_pong,
+            },
+        };
+        self.incoming.send(connection).await?;
+        Ok(MemoryClient {
+            send: send_inbound,
+            recv: recv_outbound,
+        })
+    }
+}
+
+/// Client end of an in-memory connection, exchanging encoded envelopes.
+/// Pings are answered by the transport and never reach the client.
+#[derive(Debug)]
+pub struct MemoryClient {
+    send: kanal::AsyncSender<Inbound>,
+    recv: kanal::AsyncReceiver<Outbound>,
+}
+
+impl MemoryClient {
+    pub async fn send(&self, bytes: Vec<u8>) -> eyre::Result<()> {
+        self.send.send(Inbound::Message(bytes)).await?;
+        Ok(())
+    }
+    /// Next message from the server, or `None` once the connection is closed
+    pub async fn recv(&self) -> Option<Outbound> {
+        self.recv.recv().await.ok()
+    }
+}
+
+pub struct MemoryConnection {
+    addr: SocketAddr,
+    read: MemoryRead,
+    write: MemoryWrite,
+}
+
+pub struct MemoryRead {
+    inbound: kanal::AsyncReceiver<Inbound>,
+    pongs: kanal::AsyncReceiver<()>,
+}
+
+pub struct MemoryWrite {
+    outbound: kanal::AsyncSender<Outbound>,
+    /// Pings answered on the client's behalf, kept apart from the client's own
+    /// messages so the connection ends once the client is dropped
+    pongs: kanal::AsyncSender<()>,
+}
+
+impl Connection for MemoryConnection {
+    type Read = MemoryRead;
+    type Write = MemoryWrite;
+    fn split(self) -> (Self::Read, Self::Write) {
+        (self.read, self.write)
+    }
+}
+
+impl ConnectionRead for MemoryRead {
+    async fn read(&mut self) -> eyre::Result<Option<Inbound>> {
+        tokio::select! {
+            biased;
+            inbound = self.inbound.recv() => Ok(inbound.ok()),
+            Ok(()) = self.pongs.recv() => Ok(Some(Inbound::Pong)),
+        }
+    }
+}
+
+impl ConnectionWrite for MemoryWrite {
+    async fn write(&mut self, outbound: Outbound) -> eyre::Result<()> {
+        if self.outbound.is_closed() || self.outbound.is_disconnected() {
+            eyre::bail!("memory client disconnected");
+        }
+        match outbound {
+            Outbound::Ping => self.pongs.send(()).await?,
+            outbound => self.outbound.send(outbound).await?,
+        }
+        Ok(())
+    }
+}
+
+impl Transport for MemoryTransport {
+    type Connection = MemoryConnection;
+    async fn accept(&mut self) -> eyre::Result<Option<(Self::Connection, SocketAddr)>> {
+        Ok(self.0.recv().await.ok().map(|c| {
+            let addr = c.addr;
+            (c, addr)
+        }))
+    }
+}
@@ -0,0 +1,199 @@
+use crate::{
+    account::{Account, AccountMap},
+    data::UserData,
+    matchmaking::{Accepted, LobbyVote, lobby_info, new_session},
+    queries::*,
+    queue::*,
+    send::{PrivateSignal, Receiver, SendFrame},
+    time::Ping,
+};
+use bevy::prelude::*;
+use rand::{TryRngCore, rngs::OsRng};
+use session::{
+    action::Action,
+    info::StateInfo,
+    invite::{INVITE_LEN, InviteCode},
+};
+
+/// A lobby created by a host and joined by invite code rather than matchmaking.
+#[derive(Clone, Debug, Component)]
+pub struct PrivateLobby {
+    pub code: InviteCode,
+    pub host: Entity,
+    pub members: Vec<Entity>,
+}
+
+/// Marks a player waiting in a [`PrivateLobby`], keeping them out of matchmaking.
+#[derive(Clone, Copy, Debug, Component)]
+pub struct PrivateMember;
+
+impl PrivateLobby {
+    fn notify<QC: QueueComponent, U: UserData>(
+        &self,
+        members: &InPrivateLobby<QC>,
+        lobby: &Query<LobbyMemberQuery<QC, U>>,
+    ) where
+        QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+    {
+        let capacity = QC::Lobby::capacity() as u64;
+        let mut info = lobby_info(&self.members, lobby, None);
+        for member in self.members.iter().filter_map(|e| members.get(*e).ok()) {
+            info.index = member.entity.to_index();
+            member.send_frame.send(&StateInfo::<QC::Action>::Private {
+                code: self.code,
+                members: self.members.len() as u64,
+                capacity,
+                host: member.entity == self.host,
+                lobby: info.clone(),
+            });
+        }
+    }
+}
+
+fn invite_code<'a>(lobbies: impl Iterator<Item = &'a PrivateLobby> + Clone) -> InviteCode {
+    loop {
+        let mut bytes = [0u8; INVITE_LEN];
+        OsRng.try_fill_bytes(&mut bytes).expect("OSRng Error");
+        let code = InviteCode::from_random(bytes);
+        if lobbies.clone().all(|lobby| lobby.code != code) {
+            return code;
+        }
+    }
+}
+
+fn spawn_member<QC: QueueComponent, U: UserData>(
+    commands: &mut Commands,
+    accounts: &mut AccountMap,
+    lobby: Entity,
+    (account, ping, user_data, send_frame): (Account, Ping, U, SendFrame),
+) -> Entity {
+    let entity = commands
+        .spawn((account, ping, user_data, send_frame))
+        .insert((
+            QC::default(),
+            EntityId(lobby),
+            PrivateMember,
+            LobbyVote::<QC>::default(),
+        ))
+        .id();
+    accounts.0.insert(account, entity);
+    entity
+}
+
+pub fn process_private<QC: QueueComponent, U: UserData>(
+    mut commands: Commands,
+    receiver: ResMut<Receiver<PrivateSignal<QC, U>>>,
+    mut accounts: ResMut<AccountMap>,
+    mut lobbies: Query<(Entity, &mut PrivateLobby), With<QC>>,
+    members: InPrivateLobby<QC>,
+) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+{
+    while let Ok(Some(msg)) = receiver.try_recv() {
+        match msg {
+            PrivateSignal::Create {
+                send_frame,
+                ping,
+                user_data,
+                account,
+                ..
+            } => {
+                if accounts.0.contains_key(&account) {
+                    continue;
+                }
+                let code = invite_code(lobbies.iter().map(|(_, lobby)| lobby));
+                let lobby = commands.spawn_empty().id();
+                let host = spawn_member::<QC, U>(
+                    &mut commands,
+                    &mut accounts,
+                    lobby,
+                    (account, ping, user_data, send_frame),
+                );
+                commands.entity(lobby).insert((
+                    QC::default(),
+                    PrivateLobby {
+                        code,
+                        host,
+                        members: vec![host],
+                    },
+                ));
+            }
+            PrivateSignal::Join {
+                code,
+                send_frame,
+                ping,
+                user_data,
+                account,
+                ..
+            } => {
+                if accounts.0.contains_key(&account) {
+                    continue;
+                }
+                let Some((lobby, mut private)) =
+                    lobbies.iter_mut().find(|(_, lobby)| lobby.code == code)
+                else {
+                    send_frame.send(&StateInfo::<QC::Action>::Closed);
+                    continue;
+                };
+                if private.members.len() >= QC::Lobby::capacity() {
+                    send_frame.send(&StateInfo::<QC::Action>::Closed);
+                    continue;
+                }
+                let member = spawn_member::<QC, U>(
+                    &mut commands,
+                    &mut accounts,
+                    lobby,
+                    (account, ping, user_data, send_frame),
+                );
+                private.members.push(member);
+            }
+            PrivateSignal::Start { account, .. } => {
+                let Some(host) = accounts.get(&account).and_then(|e| members.get(e).ok()) else {
+                    continue;
+                };
+                let lobby = host.session.0;
+                if let Ok((_, private)) = lobbies.get(lobby)
+                    && private.host == host.entity
+                    && let Ok(session) = QC::Lobby::try_from(&private.members)
+                {
+                    for member in private.members.iter() {
+                        commands.entity(*member).insert(Accepted);
+                    }
+                    // the lobby stays on the session, in case a member leaves before it starts
+                    let mut ec = commands.entity(lobby);
+                    ec.remove::<QC>();
+                    new_session::<QC>(&mut ec, session);
+                }
+            }
+        }
+    }
+}
+
+/// Drop members that left, hand the lobby to a new host if needed, and keep members informed
+/// of who is in the lobby and how they voted.
+/// Must run after `process_private` with its commands applied.
+pub fn update_private<QC: QueueComponent, U: UserData>(
+    mut commands: Commands,
+    mut lobbies: Query<(Entity, &mut PrivateLobby), With<QC>>,
+    members: InPrivateLobby<QC>,
+    lobby_members: Query<LobbyMemberQuery<QC, U>>,
+    voted: Query<(), (With<PrivateMember>, Changed<LobbyVote<QC>>)>,
+) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+{
+    for (entity, mut lobby) in lobbies.iter_mut() {
+        if lobby.members.iter().any(|e| !members.contains(*e)) {
+            lobby.members.retain(|e| members.contains(*e));
+            let Some(first) = lobby.members.first().copied() else {
+                commands.entity(entity).despawn();
+                continue;
+            };
+            if !lobby.members.contains(&lobby.host) {
+                lobby.host = first;
+            }
+        }
+        if lobby.is_changed() || lobby.members.iter().any(|e| voted.contains(*e)) {
+            lobby.notify(&members, &lobby_members);
+        }
+    }
+}
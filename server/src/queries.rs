@@ -1,15 +1,59 @@
 use crate::{
-    account::Account, data::UserData, matchmaking::Accepted, queue::*, send::SendFrame, time::Ping,
+    account::Account,
+    avoid::Avoid,
+    context::{SessionEnd, SessionEvents, SessionRandom},
+    data::UserData,
+    matchmaking::{Accepted, LobbyVote, Seed, SessionId},
+    private::PrivateMember,
+    queue::*,
+    replay::Recording,
+    send::SendFrame,
+    time::Ping,
+    update::Ack,
 };
 use bevy::{ecs::query::QueryData, prelude::*};
 
 pub type InQueue<'a, 'b, Q, U> = Query<'a, 'b, AccountQuery<U>, (With<Q>, Without<EntityId>)>;
-pub type InLobby<'a, 'b, QC> =
-    Query<'a, 'b, LobbyQuery, (Without<<QC as QueueComponent>::User>, Without<Accepted>)>;
+pub type InLobby<'a, 'b, QC> = Query<
+    'a,
+    'b,
+    LobbyQuery,
+    (
+        Without<<QC as QueueComponent>::User>,
+        Without<Accepted>,
+        Without<PrivateMember>,
+    ),
+>;
+pub type InPrivateLobby<'a, 'b, QC> = Query<
+    'a,
+    'b,
+    LobbyQuery,
+    (
+        Without<<QC as QueueComponent>::User>,
+        Without<Accepted>,
+        With<PrivateMember>,
+    ),
+>;
 pub type InLobbyAccepted<'a, 'b, QC> =
     Query<'a, 'b, LobbyQuery, (Without<<QC as QueueComponent>::User>, With<Accepted>)>;
+pub type LobbyChanged<'a, 'b, QC> =
+    Query<'a, 'b, &'static EntityId, (With<QC>, Or<(Added<Accepted>, Changed<LobbyVote<QC>>)>)>;
 pub type InSession<'a, 'b, QC> = Query<'a, 'b, UserQuery<QC>>;
-pub type SessionsPending<'a, 'b, QC> = Query<'a, 'b, SessionQuery<QC>, Without<Accepted>>;
+pub type MembersChanged<'a, 'b, QC> = Query<
+    'a,
+    'b,
+    &'static EntityId,
+    (
+        With<<QC as QueueComponent>::User>,
+        Or<(
+            Changed<<QC as QueueComponent>::User>,
+            Changed<SendFrame>,
+            Changed<Ack>,
+        )>,
+    ),
+>;
+pub type SessionsPending<'a, 'b, QC> =
+    Query<'a, 'b, PendingQuery<QC>, Without<<QC as QueueComponent>::Shared>>;
 pub type Sessions<'a, 'b, QC> = Query<'a, 'b, SessionQuery<QC>, With<Accepted>>;
 
 #[derive(Clone, Copy, Debug, Component)]
@@ -21,6 +65,7 @@ pub struct AccountQuery<U: UserData> {
     pub entity: Entity,
     pub account: &'static mut Account,
     pub user_data: &'static mut U,
+    pub avoid: &'static Avoid,
     pub send_frame: &'static mut SendFrame,
     pub ping: &'static Ping,
 }
@@ -43,12 +88,33 @@ pub struct UserQuery<QC: QueueComponent> {
     pub state: &'static mut QC::User,
     pub send_frame: &'static mut SendFrame,
     pub ping: &'static mut Ping,
+    pub ack: &'static mut Ack,
+}
+
+#[derive(QueryData)]
+pub struct LobbyMemberQuery<QC: QueueComponent, U: UserData> {
+    pub user_data: &'static U,
+    pub vote: &'static LobbyVote<QC>,
+    pub accepted: Has<Accepted>,
+}
+
+#[derive(QueryData)]
+pub struct PendingQuery<QC: QueueComponent> {
+    pub entity: Entity,
+    pub lobby: &'static QC::Lobby,
+    pub seed: &'static Seed,
+    pub id: &'static SessionId,
 }
 
 #[derive(QueryData)]
 #[query_data(mutable)]
 pub struct SessionQuery<QC: QueueComponent> {
     pub entity: Entity,
+    pub id: &'static SessionId,
     pub lobby: &'static mut QC::Lobby,
     pub state: &'static mut QC::Shared,
+    pub rng: &'static mut SessionRandom,
+    pub events: &'static mut SessionEvents<QC>,
+    pub end: &'static mut SessionEnd,
+    pub recording: Option<&'static mut Recording<QC>>,
 }
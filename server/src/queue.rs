@@ -3,6 +3,8 @@ use core::hash::Hash;
 use session::*;
 
 pub trait Lobby: 'static + Clone + Send + Sync + for<'a> TryFrom<&'a [Entity]> {
+    /// Number of players needed to fill the lobby
+    fn capacity() -> usize;
     fn len(&self) -> usize;
     fn entities(&self) -> impl Iterator<Item = Entity>;
     fn is_empty(&self) -> bool {
@@ -0,0 +1,407 @@
+use crate::{matchmaking::SessionId, queries::*, queue::*, time::AsStopwatch, update::ActionState};
+use bevy::prelude::*;
+use core::{future::Future, pin::Pin, time::Duration};
+use rand::SeedableRng;
+use session::{
+    action::Action,
+    context::{Context, SessionRng},
+    replay::{Checksum, Recorded, Replay, ReplayError, ReplayState, checksum},
+    state::{AsState, UserState},
+};
+use std::path::{Path, PathBuf};
+
+/// Records every session of a queue to a replay file in `dir`. Files are written by a
+/// separate task once the session is removed, whether or not it ended.
+#[derive(Debug, Resource)]
+pub struct ReplayRecorder<QC: QueueComponent> {
+    dir: PathBuf,
+    sender: kanal::Sender<(u64, Replay<QC::Action>)>,
+}
+
+impl<QC: QueueComponent> ReplayRecorder<QC> {
+    pub fn new(dir: impl Into<PathBuf>) -> (Self, Pin<Box<dyn Future<Output = ()> + Send>>) {
+        let dir = dir.into();
+        let (sender, receiver) = kanal::unbounded::<(u64, Replay<QC::Action>)>();
+        let receiver = receiver.to_async();
+        let task = Box::pin({
+            let dir = dir.clone();
+            async move {
+                while let Ok((session, replay)) = receiver.recv().await {
+                    let path = replay_path(&dir, session);
+                    let res = match replay.encode() {
+                        Ok(bytes) => tokio::fs::write(&path, bytes).await.map_err(Into::into),
+                        Err(e) => Err(eyre::Report::from(e)),
+                    };
+                    if let Err(e) = res {
+                        leptos::logging::log!("error saving replay {path:?}: {e:?}");
+                    }
+                }
+            }
+        });
+        (Self { dir, sender }, task)
+    }
+    /// Replay file of the session with id `session`
+    pub fn path(&self, session: SessionId) -> PathBuf {
+        replay_path(&self.dir, session.0)
+    }
+    pub fn save(&self, session: SessionId, replay: Replay<QC::Action>) {
+        let _ = self.sender.send((session.0, replay));
+    }
+}
+
+fn replay_path(dir: &Path, session: u64) -> PathBuf {
+    dir.join(format!("{session:016x}.replay"))
+}
+
+/// The replay of a running session, recorded as it goes.
+#[derive(Component)]
+pub struct Recording<QC: QueueComponent>(pub Replay<QC::Action>);
+
+/// Record the state each ended session finished in, before it is removed
+pub fn finish_recordings<QC: QueueComponent>(mut sessions: Sessions<QC>, users: InSession<QC>)
+where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+    QC::Shared: Checksum,
+    QC::User: Checksum,
+{
+    for session in sessions.iter_mut().filter(|s| s.end.0) {
+        if let Some(mut recording) = session.recording {
+            recording.0.finish(&ActionState::Immutable {
+                users: &users,
+                shared: &session.state,
+                lobby: &session.lobby,
+            });
+        }
+    }
+}
+
+/// Save a session's recording when it is removed, ended or not
+pub fn save_recording<QC: QueueComponent>(
+    removed: On<Remove, Recording<QC>>,
+    mut recordings: Query<(&SessionId, &mut Recording<QC>)>,
+    recorder: Res<ReplayRecorder<QC>>,
+) {
+    if let Ok((id, mut recording)) = recordings.get_mut(removed.entity) {
+        recorder.save(*id, core::mem::take(&mut recording.0));
+    }
+}
+
+/// Save the recordings of sessions still running when the server shuts down
+pub fn save_recordings_on_exit<QC: QueueComponent>(
+    mut exit: MessageReader<AppExit>,
+    mut recordings: Query<(&SessionId, &mut Recording<QC>)>,
+    recorder: Res<ReplayRecorder<QC>>,
+) {
+    if exit.read().next().is_none() {
+        return;
+    }
+    for (id, mut recording) in recordings.iter_mut() {
+        recorder.save(*id, core::mem::take(&mut recording.0));
+    }
+}
+
+/// Steps through a replay the way the server ran the session: each tick resolves its
+/// actions in order, undoing rejected ones, then updates the session and ticks its timers.
+pub struct Playback<'a, A: Action> {
+    replay: &'a Replay<A>,
+    state: ReplayState<A>,
+    rng: SessionRng,
+    elapsed: Duration,
+    tick: usize,
+    action: usize,
+}
+
+/// An action resolved during playback, with whether it was accepted
+pub type Resolved<'a, A> = (&'a Recorded<A>, Result<(), <A as Action>::Error>);
+
+impl<'a, A: Action> Playback<'a, A>
+where
+    A::User: UserState<Shared = A::Shared>,
+    A::Shared: AsStopwatch,
+{
+    pub fn new(replay: &'a Replay<A>) -> Self {
+        Self {
+            replay,
+            state: ReplayState::init(replay),
+            rng: SessionRng::from_seed(replay.seed),
+            elapsed: replay.start,
+            tick: 0,
+            action: 0,
+        }
+    }
+    pub fn state(&self) -> &ReplayState<A> {
+        &self.state
+    }
+    /// Ticks played so far
+    pub fn tick(&self) -> usize {
+        self.tick
+    }
+    /// Server time at the end of the last tick played
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    /// Play the next tick, returning the actions resolved in it, or `None` once every
+    /// tick has been played
+    pub fn step(&mut self) -> Option<Vec<Resolved<'a, A>>> {
+        let delta = *self.replay.ticks.get(self.tick)?;
+        self.elapsed += delta;
+        let mut events = Vec::new();
+        let mut resolved = Vec::new();
+        while let Some(recorded) = self
+            .replay
+            .actions
+            .get(self.action)
+            .filter(|a| a.tick == self.tick as u64)
+        {
+            self.action += 1;
+            let before = (self.state.clone(), self.rng.clone());
+            let mut ctx = Context::new(delta, self.elapsed, &mut self.rng, &mut events);
+            let result = recorded
+                .action
+                .resolve(recorded.index, &mut self.state, &mut ctx);
+            if result.is_err() {
+                (self.state, self.rng) = before;
+            }
+            resolved.push((recorded, result));
+        }
+        let mut ctx = Context::new(delta, self.elapsed, &mut self.rng, &mut events);
+        A::update(&mut self.state, &mut ctx);
+        if let Some(mut shared) = self.state.shared_mut() {
+            shared.as_mut().tick(delta);
+        }
+        self.tick += 1;
+        Some(resolved)
+    }
+}
+
+/// Re-run a session to its last recorded tick
+pub fn run<A: Action>(replay: &Replay<A>) -> ReplayState<A>
+where
+    A::User: UserState<Shared = A::Shared>,
+    A::Shared: AsStopwatch,
+{
+    let mut playback = Playback::new(replay);
+    while playback.step().is_some() {}
+    playback.state
+}
+
+/// Re-run a session and check it ends in the same state as the original
+pub fn verify<A: Action>(replay: &Replay<A>) -> Result<ReplayState<A>, ReplayError>
+where
+    A::User: UserState<Shared = A::Shared> + Checksum,
+    A::Shared: AsStopwatch + Checksum,
+{
+    let expected = replay.checksum.ok_or(ReplayError::Unfinished)?;
+    let state = run(replay);
+    let found = checksum(&state);
+    if found != expected {
+        return Err(ReplayError::Mismatch { expected, found });
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashbrown::HashMap;
+    use serde::{Deserialize, Serialize};
+    use session::{
+        context::rand_core::RngCore,
+        diff::Diff,
+        replay::checksum_of,
+        schema::{Schema, fingerprint},
+        state::SharedState,
+    };
+
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Total(u32);
+
+    impl Schema for Total {
+        const FINGERPRINT: u64 = fingerprint(b"Total");
+    }
+
+    impl Diff for Total {
+        type Delta = u32;
+        fn diff(&self, old: &Self) -> Option<u32> {
+            (self != old).then_some(self.0)
+        }
+        fn apply(&mut self, delta: u32) {
+            self.0 = delta;
+        }
+    }
+
+    #[derive(Clone, Debug, Default, Component)]
+    struct Board {
+        total: u32,
+        updates: u32,
+        clock: Duration,
+        /// Server time the last update saw
+        seen: Duration,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Player {
+        score: u32,
+        rolls: Vec<u32>,
+    }
+
+    impl AsStopwatch for Board {
+        fn pause(&mut self) {}
+        fn unpause(&mut self) {}
+        fn reset(&mut self) {
+            self.clock = Duration::ZERO;
+        }
+        fn tick(&mut self, delta: Duration) {
+            self.clock += delta;
+        }
+    }
+
+    impl SharedState for Board {
+        type Info = Total;
+        type User = Player;
+        type Settings = ();
+        fn info<S: AsState<Shared = Self>>(_index: S::Index, state: &S) -> Total {
+            Total(core::borrow::Borrow::<Board>::borrow(&state.shared()).total)
+        }
+        fn init(_seed: [u8; 32], _settings: ()) -> Self {
+            Self::default()
+        }
+    }
+
+    impl UserState for Player {
+        type Info = Total;
+        type Shared = Board;
+        fn info<S: AsState<User = Self>>(_index: S::Index, _state: &S) -> HashMap<u64, Total> {
+            HashMap::new()
+        }
+        fn init(_shared: &mut Board, users: usize) -> Vec<Self> {
+            vec![Self::default(); users]
+        }
+    }
+
+    impl Checksum for Board {
+        fn checksum(&self) -> u64 {
+            checksum_of(&(self.total, self.updates, self.clock, self.seen))
+        }
+    }
+
+    impl Checksum for Player {
+        fn checksum(&self) -> u64 {
+            checksum_of(&(self.score, &self.rolls))
+        }
+    }
+
+    /// Roll a die and add `n`, rejected for 0 after changing the state
+    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    struct Roll(u32);
+
+    impl Schema for Roll {
+        const FINGERPRINT: u64 = fingerprint(b"Roll");
+    }
+
+    impl Action for Roll {
+        type Shared = Board;
+        type User = Player;
+        type Error = ();
+        type Event = ();
+        fn update<S: AsState<User = Player, Shared = Board>>(
+            mut state: S,
+            ctx: &mut Context<'_, Self>,
+        ) {
+            if let Some(mut shared) = state.shared_mut() {
+                shared.as_mut().updates += 1;
+                shared.as_mut().seen = ctx.elapsed();
+            }
+        }
+        fn resolve<S: AsState<User = Player, Shared = Board>>(
+            self,
+            index: u64,
+            mut state: S,
+            ctx: &mut Context<'_, Self>,
+        ) -> Result<(), ()> {
+            let roll = ctx.rng().next_u32() % 6 + 1;
+            state.shared_mut().ok_or(())?.as_mut().total += self.0;
+            let mut user = state.user_mut(index).ok_or(())?;
+            user.as_mut().rolls.push(roll);
+            if self.0 == 0 {
+                return Err(());
+            }
+            user.as_mut().score += self.0 + roll;
+            Ok(())
+        }
+    }
+
+    fn recorded() -> Replay<Roll> {
+        let mut replay = Replay::new([3; 32], (), vec![4, 9]);
+        replay.action(4, Roll(2));
+        replay.tick(Duration::from_millis(16), Duration::from_millis(116));
+        replay.tick(Duration::from_millis(16), Duration::from_millis(132));
+        replay.action(9, Roll(0));
+        replay.action(9, Roll(5));
+        replay.action(4, Roll(1));
+        replay.tick(Duration::from_millis(17), Duration::from_millis(149));
+        let state = run(&replay);
+        replay.finish(&state);
+        replay
+    }
+
+    fn board(state: &ReplayState<Roll>) -> Board {
+        core::borrow::Borrow::<Board>::borrow(&state.shared()).clone()
+    }
+
+    fn player(state: &ReplayState<Roll>, index: u64) -> Player {
+        core::borrow::Borrow::<Player>::borrow(&state.user(index).unwrap()).clone()
+    }
+
+    #[test]
+    fn steps() {
+        let replay = recorded();
+        let mut playback = Playback::new(&replay);
+        let resolved = playback.step().unwrap();
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(playback.elapsed(), Duration::from_millis(116));
+        assert_eq!(board(playback.state()).seen, Duration::from_millis(116));
+        // a tick without actions still updates the session and its timers
+        assert!(playback.step().unwrap().is_empty());
+        assert_eq!(board(playback.state()).updates, 2);
+        assert_eq!(board(playback.state()).clock, Duration::from_millis(32));
+        let resolved = playback.step().unwrap();
+        let results: Vec<_> = resolved.iter().map(|(_, result)| result.is_ok()).collect();
+        assert_eq!(results, [false, true, true]);
+        assert!(playback.step().is_none());
+        assert_eq!(playback.tick(), 3);
+        assert_eq!(playback.elapsed(), Duration::from_millis(149));
+
+        let board = board(playback.state());
+        // the rejected roll is undone, its die roll included
+        assert_eq!(board.total, 8);
+        assert_eq!(board.updates, 3);
+        assert_eq!(board.clock, replay.duration());
+        assert_eq!(board.seen, replay.elapsed(2));
+        assert_eq!(player(playback.state(), 9).rolls.len(), 1);
+        assert_eq!(player(playback.state(), 4).rolls.len(), 2);
+    }
+
+    #[test]
+    fn verifies() {
+        let replay = recorded();
+        let state = verify(&replay).unwrap();
+        assert_eq!(checksum(&state), replay.checksum.unwrap());
+        let decoded = Replay::<Roll>::decode(&replay.encode().unwrap()).unwrap();
+        assert!(verify(&decoded).is_ok());
+    }
+
+    #[test]
+    fn mismatch() {
+        let mut replay = recorded();
+        replay.actions[2].action = Roll(6);
+        assert!(matches!(verify(&replay), Err(ReplayError::Mismatch { .. })));
+        let mut replay = recorded();
+        // an extra tick changes the timers even with the same actions
+        replay.ticks.push(Duration::from_millis(16));
+        assert!(matches!(verify(&replay), Err(ReplayError::Mismatch { .. })));
+        let mut replay = recorded();
+        replay.checksum = None;
+        assert!(matches!(verify(&replay), Err(ReplayError::Unfinished)));
+    }
+}
@@ -1,10 +1,18 @@
-use crate::{account::Account, data::*, queue::*, time::Ping};
+use crate::{
+    account::Account, avoid::Avoid, backpressure::Outbox, data::*, queue::*, time::Ping,
+    transport::Outbound,
+};
 use bevy::ecs::prelude::Resource;
 use core::future::Future;
-use serde::Serialize;
-use session::msg::Msg;
+use session::{
+    fair::Entropy,
+    invite::InviteCode,
+    msg::Msg,
+    protocol::{self, Framed},
+    state::SharedState,
+};
 use sqlx::*;
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
 #[derive(Clone, Debug, bevy::prelude::Resource)]
 pub struct Receiver<T>(pub kanal::Receiver<T>);
@@ -18,28 +26,47 @@ impl<T> Receiver<T> {
     }
 }
 
+fn message<T: Framed>(data: &T) -> Option<Outbound> {
+    protocol::encode(data).ok().map(Outbound::Message)
+}
+
 #[derive(Clone, Debug, bevy::prelude::Component)]
-pub struct SendFrame(kanal::Sender<fastwebsockets::Frame<'static>>);
+pub struct SendFrame(Arc<Outbox>);
 
 impl SendFrame {
-    pub fn new(sender: kanal::Sender<fastwebsockets::Frame<'static>>) -> Self {
-        Self(sender)
+    pub fn new(outbox: Outbox) -> Self {
+        Self(Arc::new(outbox))
+    }
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
     }
-    pub fn send_raw(&self, frame: fastwebsockets::Frame<'static>) {
-        let _ = self.0.send(frame);
+    /// Whether the last state update is still waiting to be written
+    pub fn state_pending(&self) -> bool {
+        self.0.state_pending()
     }
+    pub fn ping(&self) {
+        self.0.control(Outbound::Ping);
+    }
+    /// Queue the last message on the connection
+    pub fn close(&self, code: u16, reason: String) {
+        self.0.control(Outbound::Close { code, reason });
+    }
+    /// Queue a message that must arrive, in order with other messages
     pub fn send<T>(&self, data: &T)
     where
-        T: Serialize,
+        T: Framed,
     {
-        if let Ok(bytes) = bitcode::serialize(data) {
-            let frame = fastwebsockets::Frame::new(
-                true,
-                fastwebsockets::OpCode::Binary,
-                None,
-                bytes.into(),
-            );
-            let _ = self.0.send(frame);
+        if let Some(outbound) = message(data) {
+            self.0.control(outbound);
+        }
+    }
+    /// Queue a state update that replaces any update the client has not received yet
+    pub fn send_state<T>(&self, data: &T)
+    where
+        T: Framed,
+    {
+        if let Some(outbound) = message(data) {
+            self.0.state(outbound);
         }
     }
 }
@@ -50,11 +77,18 @@ pub enum QueueSignal<QC: QueueComponent, U: UserData> {
         send_frame: SendFrame,
         ping: Ping,
         user_data: U,
+        avoid: Avoid,
         account: Account,
         _phantom: PhantomData<QC>,
     },
     Accept {
         account: Account,
+        entropy: Option<Entropy>,
+        _phantom: PhantomData<QC>,
+    },
+    Vote {
+        account: Account,
+        settings: <QC::Shared as SharedState>::Settings,
         _phantom: PhantomData<QC>,
     },
     Leave {
@@ -70,9 +104,48 @@ pub struct ReconnectSignal<QC: QueueComponent> {
     pub _phantom: PhantomData<QC>,
 }
 
+#[derive(Debug)]
+pub enum PrivateSignal<QC: QueueComponent, U: UserData> {
+    Create {
+        send_frame: SendFrame,
+        ping: Ping,
+        user_data: U,
+        account: Account,
+        _phantom: PhantomData<QC>,
+    },
+    Join {
+        code: InviteCode,
+        send_frame: SendFrame,
+        ping: Ping,
+        user_data: U,
+        account: Account,
+        _phantom: PhantomData<QC>,
+    },
+    Start {
+        account: Account,
+        _phantom: PhantomData<QC>,
+    },
+}
+
+pub enum SpectateSignal<QC: QueueComponent> {
+    Join {
+        send_frame: SendFrame,
+        ping: Ping,
+        account: Account,
+        session: u64,
+        _phantom: PhantomData<QC>,
+    },
+    Leave {
+        account: Account,
+        _phantom: PhantomData<QC>,
+    },
+}
+
 pub struct ActionSignal<QC: QueueComponent> {
     pub action: QC::Action,
     pub account: Account,
+    /// Id the client chose for the action, see [`session::msg::MsgType::Action`]
+    pub id: u64,
 }
 
 pub trait Sender: Send + Sync + Clone + Resource {
@@ -80,10 +153,12 @@ pub trait Sender: Send + Sync + Clone + Resource {
     type Queue: Queue;
     type UserData: UserData;
     fn new(pool: Pool<<Self::UserData as UserData>::DB>) -> (Self, Self::Receivers);
+    fn pool(&self) -> &Pool<<Self::UserData as UserData>::DB>;
+    /// Forward a message from a connection authenticated as `account`
     fn send(
         &self,
+        account: Account,
         msg: Msg<Self::Queue>,
-        ip: std::net::SocketAddr,
         send_frame: SendFrame,
         ping: Ping,
     ) -> impl Future<Output = eyre::Result<()>> + Send;
@@ -0,0 +1,176 @@
+use crate::{
+    account::Account,
+    broadcast::{BroadcastConfig, LastSent},
+    queries::*,
+    queue::*,
+    send::{Receiver, SendFrame, SpectateSignal},
+};
+use bevy::prelude::*;
+use core::{marker::PhantomData, time::Duration};
+use session::{
+    action::Action,
+    info::{Info, StateInfo},
+};
+use std::collections::VecDeque;
+
+/// An observer attached to a session. Spectators hold no seat, so private fields are
+/// never revealed to them and hidden fields are computed for [`Entity::PLACEHOLDER`].
+#[derive(Clone, Copy, Debug, Component)]
+pub struct Spectator {
+    pub session: Entity,
+}
+
+/// How far behind the live session spectators are kept, to avoid ghosting in competitive games.
+#[derive(Debug, Resource)]
+pub struct SpectatorDelay<QC: QueueComponent> {
+    pub delay: Duration,
+    _phantom: PhantomData<QC>,
+}
+
+impl<QC: QueueComponent> SpectatorDelay<QC> {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+type Delayed<QC> = (
+    Duration,
+    Info<<QC as QueueComponent>::User, <QC as QueueComponent>::Shared>,
+);
+
+/// Spectator views waiting out the broadcast delay, and the latest one released.
+#[derive(Component)]
+pub struct SpectatorFeed<QC: QueueComponent> {
+    buffer: VecDeque<Delayed<QC>>,
+    current: Option<Info<QC::User, QC::Shared>>,
+}
+
+impl<QC: QueueComponent> Default for SpectatorFeed<QC> {
+    fn default() -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            current: None,
+        }
+    }
+}
+
+impl<QC: QueueComponent> SpectatorFeed<QC> {
+    /// Queue the session's spectator view as of `now`
+    pub fn push(&mut self, now: Duration, info: Info<QC::User, QC::Shared>) {
+        self.buffer.push_back((now, info));
+    }
+    /// Release the views that have waited out `delay`, returning whether any were
+    fn release(&mut self, now: Duration, delay: Duration) -> bool {
+        let mut released = false;
+        while self
+            .buffer
+            .front()
+            .is_some_and(|(ts, _)| now.saturating_sub(*ts) >= delay)
+        {
+            self.current = self.buffer.pop_front().map(|(_, info)| info);
+            released = true;
+        }
+        released
+    }
+}
+
+// spectators hold no seat, which keeps this disjoint from the players' `&mut SendFrame`
+pub type Spectators<'a, 'b> =
+    Query<'a, 'b, (Entity, &'static Spectator, &'static SendFrame), Without<EntityId>>;
+
+pub fn spectate<QC: QueueComponent>(
+    mut commands: Commands,
+    receiver: ResMut<Receiver<SpectateSignal<QC>>>,
+    sessions: Sessions<QC>,
+    spectators: Query<(Entity, &Spectator, &Account)>,
+) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+{
+    while let Ok(Some(msg)) = receiver.try_recv() {
+        match msg {
+            SpectateSignal::Join {
+                send_frame,
+                ping,
+                account,
+                session,
+                ..
+            } => {
+                if let Some(session) = sessions
+                    .iter()
+                    .find(|s| s.id.0 == session)
+                    .map(|s| s.entity)
+                {
+                    commands.spawn((
+                        Spectator { session },
+                        account,
+                        ping,
+                        send_frame,
+                        LastSent::<QC>::default(),
+                    ));
+                } else {
+                    send_frame.send(&StateInfo::<QC::Action>::Closed);
+                }
+            }
+            SpectateSignal::Leave { account, .. } => {
+                for (entity, spectator, _) in spectators.iter().filter(|(_, _, a)| **a == account) {
+                    if sessions.contains(spectator.session) {
+                        commands.entity(entity).despawn();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Send spectators the session views that have waited out the delay, as deltas against
+/// what each was last sent, and drop spectators whose connection closed. Views are queued
+/// by [`crate::update::update_client`], so only changes are sent, at the broadcast rate.
+pub fn update_spectators<QC: QueueComponent>(
+    mut commands: Commands,
+    time: Res<Time>,
+    delay: Option<Res<SpectatorDelay<QC>>>,
+    config: Res<BroadcastConfig<QC>>,
+    mut feeds: Query<(Entity, &mut SpectatorFeed<QC>)>,
+    spectators: Spectators,
+    mut last_sent: Query<&mut LastSent<QC>, With<Spectator>>,
+) where
+    QC::Action: Action<Shared = QC::Shared, User = QC::User>,
+{
+    let delay = delay.map(|d| d.delay).unwrap_or_default();
+    let now = time.elapsed();
+    for (entity, _, send_frame) in spectators.iter() {
+        if send_frame.is_closed() {
+            commands.entity(entity).despawn();
+        }
+    }
+    for (session, mut feed) in feeds.iter_mut() {
+        let released = feed.release(now, delay);
+        let Some(current) = &feed.current else {
+            continue;
+        };
+        for (entity, _, send_frame) in spectators.iter().filter(|(_, s, _)| s.session == session) {
+            let Ok(mut last_sent) = last_sent.get_mut(entity) else {
+                continue;
+            };
+            // spectators who just joined are caught up with the latest view
+            if !released && last_sent.has_sent() {
+                continue;
+            }
+            let resync = send_frame.state_pending();
+            if let Some(update) = last_sent.next(current.clone(), &config, resync) {
+                send_frame.send_state(&update);
+            }
+        }
+    }
+}
+
+/// Number of spectators currently attached to `session`
+pub fn spectator_count(spectators: &Spectators, session: Entity) -> u64 {
+    spectators
+        .iter()
+        .filter(|(_, s, _)| s.session == session)
+        .count() as u64
+}
@@ -0,0 +1,82 @@
+use crate::transport::{Connection, ConnectionRead, ConnectionWrite, Inbound, Outbound, Transport};
+use session::stream::{STREAM_HEADER_LEN, StreamFrame};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{
+        TcpListener, TcpStream, ToSocketAddrs,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+};
+
+/// Raw TCP transport for native clients, carrying length-prefixed [`StreamFrame`]s.
+pub struct TcpTransport(TcpListener);
+
+impl TcpTransport {
+    pub async fn bind(addr: impl ToSocketAddrs) -> std::io::Result<Self> {
+        Ok(Self(TcpListener::bind(addr).await?))
+    }
+    /// Address the transport listens on, e.g. to find the port after binding port 0
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+pub struct TcpConnection(TcpStream);
+
+pub struct TcpRead(BufReader<OwnedReadHalf>);
+
+pub struct TcpWrite(OwnedWriteHalf);
+
+impl Connection for TcpConnection {
+    type Read = TcpRead;
+    type Write = TcpWrite;
+    fn split(self) -> (Self::Read, Self::Write) {
+        let (read, write) = self.0.into_split();
+        (TcpRead(BufReader::new(read)), TcpWrite(write))
+    }
+}
+
+impl ConnectionRead for TcpRead {
+    async fn read(&mut self) -> eyre::Result<Option<Inbound>> {
+        loop {
+            let mut header = [0; STREAM_HEADER_LEN];
+            match self.0.read_exact(&mut header).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            let (tag, len) = StreamFrame::header(header)?;
+            let mut body = vec![0; len];
+            self.0.read_exact(&mut body).await?;
+            match StreamFrame::decode(tag, body)? {
+                StreamFrame::Message(bytes) => return Ok(Some(Inbound::Message(bytes))),
+                StreamFrame::Pong => return Ok(Some(Inbound::Pong)),
+                StreamFrame::Close { .. } => return Ok(None),
+                // round trips are measured by the server, so client pings need no answer
+                StreamFrame::Ping => {}
+            }
+        }
+    }
+}
+
+impl ConnectionWrite for TcpWrite {
+    async fn write(&mut self, outbound: Outbound) -> eyre::Result<()> {
+        let frame = match outbound {
+            Outbound::Message(bytes) => StreamFrame::Message(bytes),
+            Outbound::Ping => StreamFrame::Ping,
+            Outbound::Close { code, reason } => StreamFrame::Close { code, reason },
+        };
+        self.0.write_all(&frame.encode()).await?;
+        Ok(())
+    }
+}
+
+impl Transport for TcpTransport {
+    type Connection = TcpConnection;
+    async fn accept(&mut self) -> eyre::Result<Option<(Self::Connection, SocketAddr)>> {
+        let (stream, addr) = self.0.accept().await?;
+        stream.set_nodelay(true)?;
+        Ok(Some((TcpConnection(stream), addr)))
+    }
+}
@@ -0,0 +1,254 @@
+use crate::{
+    auth::Authenticated,
+    backpressure::{Backpressure, OutboxReceiver},
+    ban::{Banned, Bans},
+    queue::*,
+    send::{SendFrame, Sender},
+    time::Ping,
+};
+use core::future::Future;
+use session::{
+    msg::Msg,
+    protocol::{Envelope, Hello, PROTOCOL_CLOSE_CODE, Reject, Welcome},
+};
+use std::net::SocketAddr;
+use tokio::time::{Duration, Instant, sleep};
+
+/// A message to a client, independent of how it is carried.
+#[derive(Clone, Debug)]
+pub enum Outbound {
+    /// An encoded envelope
+    Message(Vec<u8>),
+    /// Round trip probe, answered with [`Inbound::Pong`]
+    Ping,
+    /// Last message on a connection
+    Close { code: u16, reason: String },
+}
+
+/// A message from a client, independent of how it is carried.
+#[derive(Clone, Debug)]
+pub enum Inbound {
+    /// An encoded envelope
+    Message(Vec<u8>),
+    Pong,
+}
+
+/// A single client connection, split so reading and writing run concurrently.
+pub trait Connection: Send + 'static {
+    type Read: ConnectionRead;
+    type Write: ConnectionWrite;
+    fn split(self) -> (Self::Read, Self::Write);
+}
+
+pub trait ConnectionRead: Send + 'static {
+    /// Next message from the client, or `None` once it disconnects
+    fn read(&mut self) -> impl Future<Output = eyre::Result<Option<Inbound>>> + Send;
+}
+
+pub trait ConnectionWrite: Send + 'static {
+    fn write(&mut self, outbound: Outbound) -> impl Future<Output = eyre::Result<()>> + Send;
+}
+
+/// A source of incoming client connections.
+pub trait Transport: Send + 'static {
+    type Connection: Connection;
+    /// Next connection with the client's address, or `None` once the transport shuts down
+    fn accept(
+        &mut self,
+    ) -> impl Future<Output = eyre::Result<Option<(Self::Connection, SocketAddr)>>> + Send;
+}
+
+/// Accept connections from `transport` and serve each until the transport shuts down
+pub async fn listen<S: Sender, T: Transport>(
+    mut transport: T,
+    sender: S,
+    backpressure: Backpressure,
+    bans: Bans,
+) {
+    loop {
+        match transport.accept().await {
+            Ok(Some((connection, addr))) => {
+                let sender = sender.clone();
+                let backpressure = backpressure.clone();
+                let bans = bans.clone();
+                tokio::task::spawn(async move {
+                    if let Err(e) = serve(connection, sender, backpressure, bans, addr).await {
+                        leptos::logging::log!("Error in connection {addr:?}: {e}");
+                    }
+                });
+            }
+            Ok(None) => break,
+            Err(e) => {
+                leptos::logging::log!("Error accepting connection: {e}");
+                sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+fn reject(send_frame: &SendFrame, ip: SocketAddr, reason: String) {
+    leptos::logging::log!("rejecting {ip:?}: {reason}");
+    send_frame.send(&Reject(reason.clone()));
+    send_frame.close(PROTOCOL_CLOSE_CODE, reason);
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn parse_message<Q: Queue, S: Sender<Queue = Q>>(
+    msg: &[u8],
+    ip: SocketAddr,
+    sender: &S,
+    bans: &Bans,
+    authenticated: &mut Option<Authenticated>,
+    send_frame: SendFrame,
+    ping: Ping,
+    greeted: &mut bool,
+) {
+    let envelope = match Envelope::parse(msg) {
+        Ok(envelope) => envelope,
+        Err(e) => {
+            reject(&send_frame, ip, e.to_string());
+            return;
+        }
+    };
+    if !*greeted {
+        match envelope.decode::<Hello>() {
+            Ok(Hello { fingerprint }) if fingerprint == Q::FINGERPRINT => {
+                *greeted = true;
+                send_frame.send(&Welcome { fingerprint });
+            }
+            Ok(Hello { fingerprint }) => reject(
+                &send_frame,
+                ip,
+                format!(
+                    "update required: client schema {fingerprint:016x} does not match server schema {:016x}",
+                    Q::FINGERPRINT
+                ),
+            ),
+            Err(e) => reject(&send_frame, ip, format!("handshake failed: {e}")),
+        }
+        return;
+    }
+    let msg = match envelope.decode::<Msg<Q>>() {
+        Ok(msg) => msg,
+        Err(e) => {
+            leptos::logging::log!("error parsing message for {ip:?}: {e:?}");
+            return;
+        }
+    };
+    let res =
+        match Authenticated::get::<S::UserData>(authenticated, sender.pool(), bans, msg.token, ip)
+            .await
+        {
+            Ok(account) => sender.send(account, msg, send_frame.clone(), ping).await,
+            Err(e) => Err(e),
+        };
+    if let Err(e) = res {
+        leptos::logging::log!("error sending signal for {ip:?}: {e:?}");
+        if let Some(Banned(ban)) = e.downcast_ref::<Banned>() {
+            ban.close(&send_frame);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn read<R: ConnectionRead, S: Sender>(
+    mut reader: R,
+    ip: SocketAddr,
+    sender: S,
+    bans: &Bans,
+    send_frame: &SendFrame,
+    recv_ts: tokio::sync::watch::Receiver<Option<Instant>>,
+    send_ping: tokio::sync::watch::Sender<Option<u128>>,
+    recv_ping: tokio::sync::watch::Receiver<Option<u128>>,
+) -> eyre::Result<()> {
+    let ping = Ping(recv_ping);
+    let mut greeted = false;
+    let mut authenticated = None;
+    while let Some(inbound) = reader.read().await? {
+        match inbound {
+            Inbound::Message(bytes) => {
+                parse_message(
+                    &bytes,
+                    ip,
+                    &sender,
+                    bans,
+                    &mut authenticated,
+                    send_frame.clone(),
+                    ping.clone(),
+                    &mut greeted,
+                )
+                .await;
+            }
+            Inbound::Pong => {
+                if let Some(ts) = *recv_ts.borrow() {
+                    let elapsed = Instant::now().duration_since(ts).as_millis();
+                    send_ping.send(Some(elapsed))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn write<W: ConnectionWrite>(mut writer: W, receiver: OutboxReceiver) -> eyre::Result<()> {
+    while let Some(outbound) = receiver.recv().await {
+        let close = matches!(outbound, Outbound::Close { .. });
+        writer.write(outbound).await?;
+        if close {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Run a client connection: handshake, forward its messages, and write its outgoing queue
+pub async fn serve<S: Sender, C: Connection>(
+    connection: C,
+    sender: S,
+    backpressure: Backpressure,
+    bans: Bans,
+    addr: SocketAddr,
+) -> eyre::Result<()> {
+    let (outbox, receiver) = backpressure.channel();
+    let send_frame = SendFrame::new(outbox);
+    let (reader, writer) = connection.split();
+    let mut handle = tokio::task::spawn(async move {
+        if let Err(e) = write(writer, receiver).await {
+            leptos::logging::log!("Error writing to connection: {e}");
+        }
+    });
+    if let Some(ban) = bans.check_ip(addr.ip()) {
+        // only tell a banned client why it is being turned away
+        ban.close(&send_frame);
+        let _ = handle.await;
+        return Ok(());
+    }
+    let (send_ts, recv_ts) = tokio::sync::watch::channel(None);
+    let (send_ping, recv_ping) = tokio::sync::watch::channel(None);
+    let send_heartbeat = send_frame.clone();
+    let heartbeat = tokio::task::spawn(async move {
+        while !send_heartbeat.is_closed() {
+            send_ts.send(Some(Instant::now()))?;
+            send_heartbeat.ping();
+            sleep(Duration::from_secs(3)).await;
+        }
+        Ok::<_, eyre::Report>(())
+    });
+    // the writer stops early once it sends a close or the connection is closed for backpressure
+    let res = tokio::select! {
+        res = read(
+            reader,
+            addr,
+            sender.clone(),
+            &bans,
+            &send_frame,
+            recv_ts,
+            send_ping,
+            recv_ping.clone(),
+        ) => res,
+        _ = &mut handle => Ok(()),
+    };
+    heartbeat.abort();
+    handle.abort();
+    res
+}
@@ -1,8 +1,60 @@
-use crate::{account::AccountMap, queries::*, queue::*, send::*, time::*};
-use bevy::prelude::*;
-use session::{action::Action, info::*, state::*};
+use crate::{
+    account::AccountMap,
+    broadcast::{Broadcast, BroadcastConfig, LastSent},
+    fair::Committed,
+    queries::*,
+    queue::*,
+    send::*,
+    spectate::{SpectatorFeed, Spectators, spectator_count},
+    time::*,
+};
+use bevy::{ecs::component::Tick, prelude::*};
+use hashbrown::HashMap;
+use session::{action::Action, context::Context, info::*, protocol::ActionReply, state::*};
 use std::borrow::Borrow;
 
+/// Id of a user's latest processed action, see [`session::Prediction`]
+#[derive(Clone, Copy, Debug, Default, Component)]
+pub struct Ack(pub u64);
+
+/// Components as they were before an action first mutated them, with their change
+/// ticks, so a rejected action can be undone as a whole
+pub struct Journal<QC: QueueComponent> {
+    session: Option<Entity>,
+    shared: Option<(QC::Shared, Tick)>,
+    users: HashMap<Entity, (QC::User, Tick)>,
+}
+
+impl<QC: QueueComponent> Default for Journal<QC> {
+    fn default() -> Self {
+        Self {
+            session: None,
+            shared: None,
+            users: HashMap::new(),
+        }
+    }
+}
+
+impl<QC: QueueComponent> Journal<QC> {
+    /// Restore every component recorded in the journal. Change ticks are restored too,
+    /// so an undone action doesn't cause a broadcast of unchanged state.
+    pub fn rollback(self, sessions: &mut Sessions<QC>, users: &mut InSession<QC>) {
+        if let Some(session) = self.session
+            && let Some((shared, changed)) = self.shared
+            && let Ok(mut session) = sessions.get_mut(session)
+        {
+            *session.state.bypass_change_detection() = shared;
+            session.state.set_last_changed(changed);
+        }
+        for (entity, (state, changed)) in self.users {
+            if let Ok(mut user) = users.get_mut(entity) {
+                *user.state.bypass_change_detection() = state;
+                user.state.set_last_changed(changed);
+            }
+        }
+    }
+}
+
 pub type ActionStateInfo<'a, QC> =
     Info<<ActionState<'a, QC> as AsState>::User, <ActionState<'a, QC> as AsState>::Shared>;
 
@@ -14,6 +66,8 @@ where
         users: &'a mut InSession<'a, 'a, QC>,
         shared: Mut<'a, QC::Shared>,
         lobby: Mut<'a, QC::Lobby>,
+        /// Records state before it is first mutated, if the changes may be undone
+        journal: Option<&'a mut Journal<QC>>,
     },
     Immutable {
         users: &'a InSession<'a, 'a, QC>,
@@ -26,7 +80,7 @@ impl<'a, QC: QueueComponent> ActionState<'a, QC>
 where
     QC::Action: Action<Shared = QC::Shared, User = QC::User>,
 {
-    fn info(
+    pub fn info(
         session_id: Entity,
         user_id: Entity,
         sessions: &'a Sessions<'a, 'a, QC>,
@@ -46,41 +100,98 @@ where
     }
     fn update(
         session_id: Entity,
+        time: &Time,
         sessions: &'a mut Sessions<'a, 'a, QC>,
         users: &'a mut InSession<'a, 'a, QC>,
     ) -> Option<()> {
         let session = sessions.get_mut(session_id).ok()?;
         let shared = session.state;
         let lobby = session.lobby;
-        QC::Action::update(Self::Mutable {
-            users,
-            shared,
-            lobby,
-        });
+        let mut rng = session.rng;
+        let mut events = session.events;
+        let mut end = session.end;
+        let mut ctx = Context::new(time.delta(), time.elapsed(), &mut rng.0, &mut events.0);
+        QC::Action::update(
+            Self::Mutable {
+                users,
+                shared,
+                lobby,
+                journal: None,
+            },
+            &mut ctx,
+        );
+        if ctx.is_ended() {
+            end.0 = true;
+        }
+        if let Some(mut recording) = session.recording {
+            recording.0.tick(time.delta(), time.elapsed());
+        }
         Some(())
     }
+    /// Resolve an action and reply to its sender, returning `false` if it was rejected,
+    /// in which case its changes are left in `journal` to be rolled back
     fn resolve(
         msg: ActionSignal<QC>,
         accounts: impl AsRef<AccountMap>,
+        time: &Time,
+        journal: &'a mut Journal<QC>,
         sessions: &'a mut Sessions<'a, 'a, QC>,
         users: &'a mut InSession<'a, 'a, QC>,
-    ) -> eyre::Result<()> {
+    ) -> eyre::Result<bool> {
+        let ActionSignal {
+            action,
+            account,
+            id,
+        } = msg;
         let user_id = accounts
             .as_ref()
-            .get(&msg.account)
+            .get(&account)
             .ok_or(eyre::eyre!("Could not find user id"))?;
-        let session_id = users.get(user_id)?.session.0;
+        let mut user = users.get_mut(user_id)?;
+        // acknowledged even if rejected, so the client stops predicting it
+        user.ack.0 = id;
+        let send_frame = user.send_frame.clone();
+        let session_id = user.session.0;
         let session = sessions.get_mut(session_id)?;
         let shared = session.state;
         let lobby = session.lobby;
-        msg.action.resolve(
+        let mut rng = session.rng;
+        let mut events = session.events;
+        let mut end = session.end;
+        journal.session = Some(session_id);
+        if let Some(mut recording) = session.recording {
+            recording.0.action(user_id.to_index(), action);
+        }
+        // randomness drawn and events emitted by a rejected action are discarded too
+        let snapshot = rng.0.clone();
+        let mut emitted = Vec::new();
+        let mut ctx = Context::new(time.delta(), time.elapsed(), &mut rng.0, &mut emitted);
+        let result = action.resolve(
             user_id.to_index(),
             Self::Mutable {
                 users,
                 shared,
                 lobby,
+                journal: Some(journal),
             },
-        )
+            &mut ctx,
+        );
+        let ended = ctx.is_ended();
+        match &result {
+            Ok(()) => {
+                events.0.append(&mut emitted);
+                end.0 |= ended;
+            }
+            Err(error) => {
+                rng.0 = snapshot;
+                leptos::logging::log!(
+                    "rejected action {id} {action:?} from {account:?}: {error:?}"
+                );
+            }
+        }
+        let accepted = result.is_ok();
+        send_frame.send(&ActionReply::<QC::Action> { id, result });
+        Ok(accepted)
     }
 }
 
@@ -106,10 +217,17 @@ where
     }
     fn user_mut(&mut self, i: u64) -> Option<impl AsMut<Self::User>> {
         let i = Self::Index::from_index(i)?;
-        let Self::Mutable { users, .. } = self else {
+        let Self::Mutable { users, journal, .. } = self else {
             return None;
         };
-        users.get_mut(i).ok().map(|u| u.state)
+        let user = users.get_mut(i).ok()?;
+        if let Some(journal) = journal {
+            journal
+                .users
+                .entry(i)
+                .or_insert_with(|| (user.state.as_ref().clone(), user.state.last_changed()));
+        }
+        Some(user.state)
     }
     fn shared(&self) -> impl Borrow<Self::Shared> {
         match self {
@@ -118,9 +236,17 @@ where
         }
     }
     fn shared_mut(&mut self) -> Option<impl AsMut<Self::Shared>> {
-        let Self::Mutable { shared, .. } = self else {
+        let Self::Mutable {
+            shared, journal, ..
+        } = self
+        else {
             return None;
         };
+        if let Some(journal) = journal
+            && journal.shared.is_none()
+        {
+            journal.shared = Some((shared.as_ref().clone(), shared.last_changed()));
+        }
         Some(shared)
     }
     fn indices(&self) -> impl Iterator<Item = u64> {
@@ -132,21 +258,55 @@ where
     }
 }
 
-pub fn update_client<QC: QueueComponent>(sessions: Sessions<QC>, users: InSession<QC>)
-where
+pub fn update_client<QC: QueueComponent>(
+    time: Res<Time>,
+    config: Res<BroadcastConfig<QC>>,
+    sessions: Sessions<QC>,
+    users: InSession<QC>,
+    mut broadcasts: Query<(
+        &mut Broadcast<QC>,
+        &mut SpectatorFeed<QC>,
+        Option<&Committed>,
+    )>,
+    mut last_sent: Query<&mut LastSent<QC>>,
+    spectators: Spectators,
+) where
     QC::Action: Action<Shared = QC::Shared, User = QC::User>,
 {
     let s: Vec<_> = sessions
         .iter()
-        .map(|s| (s.entity, s.lobby.clone()))
+        .map(|s| (s.entity, *s.id, s.lobby.clone()))
         .collect();
-    for (session, lobby) in s.into_iter() {
+    for (session, id, lobby) in s.into_iter() {
+        let watching = spectator_count(&spectators, session);
+        let Ok((mut broadcast, mut feed, committed)) = broadcasts.get_mut(session) else {
+            continue;
+        };
+        if !broadcast.ready(watching, time.elapsed(), &config) {
+            continue;
+        }
+        if watching > 0
+            && let Some(mut info) =
+                ActionState::info(session, Entity::PLACEHOLDER, &sessions, &users)
+        {
+            info.session = id.0;
+            info.spectators = watching;
+            info.commitment = committed.map(|c| c.commitment);
+            feed.push(time.elapsed(), info);
+        }
         for user in lobby.entities() {
-            if let Some(info) = ActionState::info(session, user, &sessions, &users)
+            if let Some(mut info) = ActionState::info(session, user, &sessions, &users)
+                && let Ok(mut last_sent) = last_sent.get_mut(user)
                 && let Ok(user) = users.get(user)
             {
-                leptos::logging::log!("{info:?}");
-                user.send_frame.send(&StateInfo::Session(info));
+                info.session = id.0;
+                info.spectators = watching;
+                info.ack = user.ack.0;
+                info.commitment = committed.map(|c| c.commitment);
+                let resync = user.send_frame.state_pending();
+                if let Some(update) = last_sent.next(info, &config, resync) {
+                    user.send_frame.send_state(&update);
+                }
             }
         }
     }
@@ -163,18 +323,29 @@ pub fn process_actions<QC: QueueComponent>(
     QC::Shared: AsStopwatch,
 {
     while let Ok(Some(msg)) = actions.try_recv() {
-        let _ = ActionState::resolve(
+        let mut journal = Journal::default();
+        let accepted = ActionState::resolve(
             msg,
             &accounts,
+            &time,
+            &mut journal,
             &mut sessions.reborrow(),
             &mut users.reborrow(),
         );
+        if let Ok(false) = accepted {
+            journal.rollback(&mut sessions, &mut users);
+        }
     }
     let s: Vec<_> = sessions.iter().map(|s| s.entity).collect();
     for session in s.into_iter() {
-        ActionState::update(session, &mut sessions.reborrow(), &mut users.reborrow());
+        ActionState::update(
+            session,
+            &time,
+            &mut sessions.reborrow(),
+            &mut users.reborrow(),
+        );
     }
     for mut session in sessions.iter_mut() {
-        session.state.tick(time.delta());
+        session.state.bypass_change_detection().tick(time.delta());
     }
 }
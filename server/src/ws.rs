@@ -1,152 +1,121 @@
 use crate::{
-    queue::*,
-    send::{SendFrame, Sender},
-    time::Ping,
+    backpressure::Backpressure,
+    ban::{BAN_CLOSE_CODE, Bans},
+    send::Sender,
+    transport::{Connection, ConnectionRead, ConnectionWrite, Inbound, Outbound, serve},
 };
 use axum::{
+    Extension,
     extract::{ConnectInfo, State},
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 use fastwebsockets::{
-    FragmentCollectorRead, Frame, OpCode, Payload, WebSocketError, WebSocketWrite, upgrade,
+    FragmentCollectorRead, Frame, OpCode, Payload, WebSocket, WebSocketError, WebSocketRead,
+    WebSocketWrite, upgrade,
+};
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
+    sync::Mutex,
 };
-use session::{msg::Msg, token::ClientToken};
-use tokio::time::{Duration, Instant, sleep};
 
-async fn parse_message<Q: Queue, S: Sender<Queue = Q>>(
-    msg: &[u8],
-    ip: std::net::SocketAddr,
-    sender: &S,
-    send_frame: SendFrame,
-    ping: Ping,
-) -> Option<ClientToken> {
-    match bincode::serde::decode_from_slice::<Msg<Q>, _>(msg, bincode::config::standard()) {
-        Ok((msg, _)) => {
-            let token = msg.token;
-            if let Err(e) = sender.send(msg, ip, send_frame, ping).await {
-                leptos::logging::log!("error sending signal for {ip:?}: {e:?}");
-            }
-            Some(token)
-        }
-        Err(e) => {
-            leptos::logging::log!("error parsing message for {ip:?}: {e:?}");
-            None
-        }
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Stream for T {}
+
+/// Close frame with `reason` truncated to fit the control frame limit
+fn close_frame(code: u16, reason: &str) -> Frame<'static> {
+    let mut end = reason.len().min(123);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
     }
+    Frame::close(code, &reason.as_bytes()[..end])
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn read<F: tokio::io::AsyncRead + Unpin, S: Sender>(
-    mut ws: FragmentCollectorRead<F>,
-    token: &mut Option<ClientToken>,
-    ip: std::net::SocketAddr,
-    sender: S,
-    send_frame: &SendFrame,
-    recv_ts: tokio::sync::watch::Receiver<Option<Instant>>,
-    send_ping: tokio::sync::watch::Sender<Option<u128>>,
-    recv_ping: tokio::sync::watch::Receiver<Option<u128>>,
-) -> eyre::Result<()> {
-    let ping = Ping(recv_ping);
-    loop {
-        let mut frame = ws
-            .read_frame::<_, WebSocketError>(&mut move |frame| async {
-                // for handling obligated sends
-                send_frame.send_raw(frame);
-                Ok(())
-            })
-            .await?;
-        match frame.opcode {
-            OpCode::Close => break,
-            OpCode::Binary => {
-                *token = parse_message(
-                    frame.payload.to_mut(),
-                    ip,
-                    &sender,
-                    send_frame.clone(),
-                    ping.clone(),
-                )
-                .await;
-            }
-            OpCode::Pong => {
-                if let Some(ts) = *recv_ts.borrow() {
-                    let elapsed = Instant::now().duration_since(ts).as_millis();
-                    send_ping.send(Some(elapsed))?;
-                }
-            }
-            _ => {}
-        }
-    }
-    Ok(())
+/// A websocket client connection
+pub struct WsConnection<S: Stream>(pub WebSocket<S>);
+
+pub struct WsRead<S: Stream> {
+    ws: FragmentCollectorRead<ReadHalf<S>>,
+    // shared with the writer so pongs and close replies go out as soon as they are read
+    write: Arc<Mutex<WebSocketWrite<WriteHalf<S>>>>,
 }
 
-async fn write<S: tokio::io::AsyncWrite + Unpin>(
-    mut ws: WebSocketWrite<S>,
-    receive_frame: kanal::AsyncReceiver<Frame<'static>>,
-) -> Result<(), WebSocketError> {
-    while let Ok(frame) = receive_frame.recv().await {
-        ws.write_frame(frame).await?;
+pub struct WsWrite<S: Stream>(Arc<Mutex<WebSocketWrite<WriteHalf<S>>>>);
+
+impl<S: Stream> Connection for WsConnection<S> {
+    type Read = WsRead<S>;
+    type Write = WsWrite<S>;
+    fn split(self) -> (Self::Read, Self::Write) {
+        let (read, write): (WebSocketRead<_>, _) = self.0.split(tokio::io::split);
+        let write = Arc::new(Mutex::new(write));
+        let read = WsRead {
+            ws: FragmentCollectorRead::new(read),
+            write: write.clone(),
+        };
+        (read, WsWrite(write))
     }
-    Ok(())
 }
 
-async fn handle_client<S: Sender>(
-    fut: upgrade::UpgradeFut,
-    sender: S,
-    addr: std::net::SocketAddr,
-) -> eyre::Result<()> {
-    let (send_frame, receive_frame) = kanal::bounded::<Frame>(100);
-    let send_heartbeat = send_frame.clone_async();
-    let (send_ts, recv_ts) = tokio::sync::watch::channel(None);
-    let (send_ping, recv_ping) = tokio::sync::watch::channel(None);
-    let send_frame = SendFrame::new(send_frame);
-    let ws = fut.await?;
-    let (ws_read, ws_write) = ws.split(tokio::io::split);
-    let ws_read = FragmentCollectorRead::new(ws_read);
-    let handle = tokio::task::spawn(async move {
-        if let Err(e) = write(ws_write, receive_frame.to_async()).await {
-            leptos::logging::log!("Error in websocket connection: {e}");
-        }
-    });
-    let heartbeat = tokio::task::spawn(async move {
-        while {
-            let frame = Frame::new(true, OpCode::Ping, None, Payload::Owned(Vec::new()));
-            send_ts.send(Some(Instant::now()))?;
-            send_heartbeat.send(frame).await?;
-            Ok::<_, eyre::Report>(())
-        }
-        .is_ok()
-        {
-            sleep(Duration::from_secs(3)).await;
+impl<S: Stream> ConnectionRead for WsRead<S> {
+    async fn read(&mut self) -> eyre::Result<Option<Inbound>> {
+        loop {
+            let write = &self.write;
+            let frame = self
+                .ws
+                .read_frame::<_, WebSocketError>(&mut move |frame| async move {
+                    // for handling obligated sends
+                    write.lock().await.write_frame(frame).await
+                })
+                .await?;
+            match frame.opcode {
+                OpCode::Close => return Ok(None),
+                OpCode::Binary => return Ok(Some(Inbound::Message(frame.payload.to_vec()))),
+                OpCode::Pong => return Ok(Some(Inbound::Pong)),
+                _ => {}
+            }
         }
-        Ok::<_, eyre::Report>(())
-    });
-    let mut token: Option<ClientToken> = None;
-    let res = read(
-        ws_read,
-        &mut token,
-        addr,
-        sender.clone(),
-        &send_frame,
-        recv_ts,
-        send_ping,
-        recv_ping.clone(),
-    )
-    .await;
-    heartbeat.abort();
-    handle.abort();
-    res
+    }
+}
+
+impl<S: Stream> ConnectionWrite for WsWrite<S> {
+    async fn write(&mut self, outbound: Outbound) -> eyre::Result<()> {
+        let frame = match outbound {
+            Outbound::Message(bytes) => Frame::new(true, OpCode::Binary, None, bytes.into()),
+            Outbound::Ping => Frame::new(true, OpCode::Ping, None, Payload::Owned(Vec::new())),
+            Outbound::Close { code, reason } => close_frame(code, &reason),
+        };
+        self.0.lock().await.write_frame(frame).await?;
+        Ok(())
+    }
 }
 
 pub async fn ws_handler<S: Sender>(
     State(sender): State<S>,
+    Extension(backpressure): Extension<Backpressure>,
+    Extension(bans): Extension<Bans>,
     ConnectInfo(addr): ConnectInfo<std::net::SocketAddr>,
     ws: upgrade::IncomingUpgrade,
-) -> impl IntoResponse {
-    let (response, fut) = ws.upgrade().unwrap();
+) -> Response {
+    let ban = bans.check_ip(addr.ip());
+    let (response, fut) = match ws.upgrade() {
+        Ok(upgrade) => upgrade,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
     tokio::task::spawn(async move {
-        if let Err(e) = handle_client(fut, sender, addr).await {
+        let res = match (fut.await, ban) {
+            // browsers can't read a refused upgrade, so the reason goes in a close frame
+            (Ok(mut ws), Some(ban)) => ws
+                .write_frame(close_frame(BAN_CLOSE_CODE, &ban.reason))
+                .await
+                .map_err(Into::into),
+            (Ok(ws), None) => serve(WsConnection(ws), sender, backpressure, bans, addr).await,
+            (Err(e), _) => Err(e.into()),
+        };
+        if let Err(e) = res {
             leptos::logging::log!("Error in websocket connection: {e}");
         }
     });
-    response
+    response.into_response()
 }
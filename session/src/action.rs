@@ -1,12 +1,21 @@
-use crate::{msg::Message, state::*};
+use crate::{context::Context, msg::Message, schema::Schema, state::*};
 
-pub trait Action: Sized + Copy + Message {
+pub trait Action: Sized + Copy + Message + Schema {
     type Shared: SharedState;
     type User: UserState;
-    fn update<S: AsState<User = Self::User, Shared = Self::Shared>>(state: S);
+    /// Why an action was rejected, sent back to the player who took it
+    type Error: Message + Schema;
+    /// Transient events emitted through [`Context::emit`]
+    type Event: Message + Schema;
+    /// Called every tick for each running session
+    fn update<S: AsState<User = Self::User, Shared = Self::Shared>>(
+        state: S,
+        ctx: &mut Context<'_, Self>,
+    );
     fn resolve<S: AsState<User = Self::User, Shared = Self::Shared>>(
         self,
         index: u64,
         state: S,
-    ) -> eyre::Result<()>;
+        ctx: &mut Context<'_, Self>,
+    ) -> Result<(), Self::Error>;
 }
@@ -1,20 +1,21 @@
+use crate::protocol::*;
 use codee::{Decoder, Encoder};
-use serde::{Deserialize, Serialize};
 
-pub struct BitcodeCodec;
+/// Codec for the framed protocol, shared by every client and the server
+pub struct ProtocolCodec;
 
-impl<T: Serialize> Encoder<T> for BitcodeCodec {
-    type Error = bitcode::Error;
+impl<T: Framed> Encoder<T> for ProtocolCodec {
+    type Error = ProtocolError;
     type Encoded = Vec<u8>;
     fn encode(val: &T) -> Result<Self::Encoded, Self::Error> {
-        bitcode::serialize(val)
+        encode(val)
     }
 }
 
-impl<T: for<'de> Deserialize<'de>> Decoder<T> for BitcodeCodec {
-    type Error = bitcode::Error;
+impl<T: Framed> Decoder<T> for ProtocolCodec {
+    type Error = ProtocolError;
     type Encoded = [u8];
     fn decode(val: &Self::Encoded) -> Result<T, Self::Error> {
-        bitcode::deserialize(val)
+        decode(val)
     }
 }
@@ -0,0 +1,73 @@
+use crate::*;
+use core::time::Duration;
+use rand_chacha::ChaCha8Rng;
+
+pub use rand_chacha::rand_core;
+
+/// Deterministic random numbers for a session, seeded from the seed drawn at matchmaking
+pub type SessionRng = ChaCha8Rng;
+
+/// A transient event and who it is for, see [`Context::emit`]
+#[derive(Clone, Debug)]
+pub struct Emitted<E> {
+    /// The seat to deliver to, or every seat when `None`
+    pub to: Option<u64>,
+    pub event: E,
+}
+
+/// Everything an action can use besides the session state: time, randomness,
+/// transient events and ending the session.
+pub struct Context<'a, A: Action> {
+    delta: Duration,
+    elapsed: Duration,
+    rng: &'a mut SessionRng,
+    events: &'a mut Vec<Emitted<A::Event>>,
+    ended: bool,
+}
+
+impl<'a, A: Action> Context<'a, A> {
+    pub fn new(
+        delta: Duration,
+        elapsed: Duration,
+        rng: &'a mut SessionRng,
+        events: &'a mut Vec<Emitted<A::Event>>,
+    ) -> Self {
+        Self {
+            delta,
+            elapsed,
+            rng,
+            events,
+            ended: false,
+        }
+    }
+    /// Time since the previous tick
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+    /// Time since the server started
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+    pub fn rng(&mut self) -> &mut SessionRng {
+        self.rng
+    }
+    /// Send `event` to every seat in the session. Events are not part of [`Info`],
+    /// so clients that connect later never see them, and spectators do not receive them.
+    pub fn emit(&mut self, event: A::Event) {
+        self.events.push(Emitted { to: None, event });
+    }
+    /// End the session once this tick is over, sending every seat its final state
+    pub fn end(&mut self) {
+        self.ended = true;
+    }
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+    /// Send `event` to seat `index` only
+    pub fn emit_to(&mut self, index: u64, event: A::Event) {
+        self.events.push(Emitted {
+            to: Some(index),
+            event,
+        });
+    }
+}
@@ -0,0 +1,109 @@
+use crate::msg::Message;
+
+/// Field-level differences between two values, used to send only what changed.
+pub trait Diff: Sized {
+    type Delta: Message;
+    /// Changes turning `old` into `self`, or `None` when nothing changed
+    fn diff(&self, old: &Self) -> Option<Self::Delta>;
+    fn apply(&mut self, delta: Self::Delta);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use hashbrown::HashMap;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Score(u32);
+
+    impl Schema for Score {
+        const FINGERPRINT: u64 = fingerprint(b"Score");
+    }
+
+    impl Diff for Score {
+        type Delta = u32;
+        fn diff(&self, old: &Self) -> Option<u32> {
+            (self != old).then_some(self.0)
+        }
+        fn apply(&mut self, delta: u32) {
+            self.0 = delta;
+        }
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Board;
+
+    #[derive(Clone, Debug, Default)]
+    struct Player;
+
+    impl SharedState for Board {
+        type Info = Score;
+        type User = Player;
+        type Settings = ();
+        fn info<S: AsState<Shared = Self>>(_index: S::Index, _state: &S) -> Score {
+            Score::default()
+        }
+        fn init(_seed: [u8; 32], _settings: ()) -> Self {
+            Self
+        }
+    }
+
+    impl UserState for Player {
+        type Info = Score;
+        type Shared = Board;
+        fn info<S: AsState<User = Self>>(_index: S::Index, _state: &S) -> HashMap<u64, Score> {
+            HashMap::new()
+        }
+        fn init(_shared: &mut Board, users: usize) -> Vec<Self> {
+            vec![Self; users]
+        }
+    }
+
+    fn info(users: &[(u64, u32)], shared: u32, spectators: u64, ack: u64) -> Info<Player, Board> {
+        Info {
+            users: users.iter().map(|(i, s)| (*i, Score(*s))).collect(),
+            shared: Score(shared),
+            index: 1,
+            session: 7,
+            spectators,
+            ack,
+            commitment: None,
+        }
+    }
+
+    fn roundtrip(old: Info<Player, Board>, new: Info<Player, Board>) {
+        let delta = new.diff(&old).expect("a change");
+        let mut applied = old;
+        applied.apply(delta);
+        assert_eq!(applied.users, new.users);
+        assert_eq!(applied.shared, new.shared);
+        assert_eq!(applied.spectators, new.spectators);
+        assert_eq!(applied.ack, new.ack);
+    }
+
+    #[test]
+    fn unchanged() {
+        let old = info(&[(1, 3), (2, 4)], 7, 0, 2);
+        assert!(old.clone().diff(&old).is_none());
+    }
+
+    #[test]
+    fn changed() {
+        let old = info(&[(1, 3), (2, 4)], 7, 0, 2);
+        roundtrip(old.clone(), info(&[(1, 5), (2, 4)], 9, 0, 2));
+        roundtrip(old.clone(), info(&[(1, 3), (2, 4)], 7, 3, 2));
+        roundtrip(old, info(&[(1, 3), (2, 4)], 7, 0, 3));
+    }
+
+    #[test]
+    fn seats() {
+        let old = info(&[(1, 3), (2, 4)], 7, 0, 2);
+        let new = info(&[(2, 4), (3, 1)], 7, 0, 2);
+        let delta = new.diff(&old).expect("a change");
+        assert!(delta.users.is_empty());
+        assert_eq!(delta.removed, vec![1]);
+        roundtrip(old, new);
+    }
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Randomness a player contributes to the session seed when accepting a lobby
+pub type Entropy = [u8; 32];
+
+/// Hash of the server's seed, sent before the session starts so the server cannot
+/// change the seed later without clients noticing
+pub fn commit(seed: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(b"ilium-commit")
+        .chain_update(seed)
+        .finalize()
+        .into()
+}
+
+/// Session seed drawn from the server's seed and each player's entropy, in lobby order.
+/// Without any entropy this is the server's seed unchanged.
+pub fn mix(seed: [u8; 32], entropy: &[Entropy]) -> [u8; 32] {
+    if entropy.is_empty() {
+        return seed;
+    }
+    let mut hasher = Sha256::new().chain_update(b"ilium-mix").chain_update(seed);
+    for entropy in entropy {
+        hasher.update(entropy);
+    }
+    hasher.finalize().into()
+}
+
+/// Everything needed to check a session's randomness, sent when it ends
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Reveal {
+    /// The server's seed, committed to by [`crate::Info::commitment`]
+    pub seed: [u8; 32],
+    /// Entropy contributed by the players, in lobby order
+    pub entropy: Vec<Entropy>,
+}
+
+impl Reveal {
+    /// Seed the session's randomness was drawn from, see [`crate::SessionRng`]
+    pub fn session_seed(&self) -> [u8; 32] {
+        mix(self.seed, &self.entropy)
+    }
+    /// Whether the revealed seed is the one the server committed to
+    pub fn verify(&self, commitment: &[u8; 32]) -> bool {
+        commit(&self.seed) == *commitment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify() {
+        let reveal = Reveal {
+            seed: [1; 32],
+            entropy: vec![[2; 32]],
+        };
+        assert!(reveal.verify(&commit(&[1; 32])));
+        assert!(!reveal.verify(&commit(&[2; 32])));
+        let forged = Reveal {
+            seed: [3; 32],
+            ..reveal
+        };
+        assert!(!forged.verify(&commit(&[1; 32])));
+    }
+
+    #[test]
+    fn mixed() {
+        let seed = [1; 32];
+        assert_eq!(mix(seed, &[]), seed);
+        let mixed = mix(seed, &[[2; 32], [3; 32]]);
+        assert_ne!(mixed, seed);
+        assert_eq!(mixed, mix(seed, &[[2; 32], [3; 32]]));
+        // every player's entropy counts, in lobby order
+        assert_ne!(mixed, mix(seed, &[[3; 32], [2; 32]]));
+        assert_ne!(mixed, mix(seed, &[[2; 32], [4; 32]]));
+        assert_ne!(mixed, mix([5; 32], &[[2; 32], [3; 32]]));
+        let reveal = Reveal {
+            seed,
+            entropy: vec![[2; 32], [3; 32]],
+        };
+        assert_eq!(reveal.session_seed(), mixed);
+    }
+}
@@ -1,16 +1,52 @@
 use crate::*;
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize};
 
 pub type AsInfo<Q> =
     Info<<<Q as AsQueue>::Action as Action>::User, <<Q as AsQueue>::Action as Action>::Shared>;
 
+/// Everything the server sends to a client about its place in a queue
 #[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(bound = "I: Serialize + DeserializeOwned")]
-pub enum StateInfo<I: 'static + Message> {
+#[serde(bound = "")]
+pub enum StateInfo<A: Action> {
     Closed,
     Queue,
-    Lobby,
-    Session(I),
+    /// Waiting in a private lobby
+    Private {
+        code: InviteCode,
+        members: u64,
+        capacity: u64,
+        host: bool,
+        /// Members, their votes and the settings the session would start with
+        lobby: LobbyInfo<SettingsOf<A>>,
+    },
+    Lobby(LobbyInfo<SettingsOf<A>>),
+    Session(Info<A::User, A::Shared>),
+    /// Changes since the last `Session` or `SessionDelta` sent on this connection
+    SessionDelta(InfoDelta<A::User, A::Shared>),
+    /// The session is over, with its final state and, if it was provably fair, its seed
+    Ended {
+        info: Info<A::User, A::Shared>,
+        reveal: Option<Reveal>,
+    },
+}
+
+impl<A: Action> StateInfo<A> {
+    /// Fold an update from the server into this cached state.
+    /// Returns `false` for a delta with no session to apply it to, in which case
+    /// the cache is left as is until the next full snapshot.
+    pub fn update(&mut self, update: Self) -> bool {
+        match (self, update) {
+            (Self::Session(info), Self::SessionDelta(delta)) => {
+                info.apply(delta);
+                true
+            }
+            (_, Self::SessionDelta(_)) => false,
+            (this, update) => {
+                *this = update;
+                true
+            }
+        }
+    }
 }
 
 /// Trait for info serialized to the client
@@ -19,4 +55,107 @@ pub struct Info<U: UserState, S: SharedState> {
     pub users: hashbrown::HashMap<u64, U::Info>,
     pub shared: S::Info,
     pub index: u64,
+    /// Id of the session, for sharing with spectators. Unlike entity ids it is never reused,
+    /// so a stale id can't attach a spectator to a later session
+    pub session: u64,
+    pub spectators: u64,
+    /// Id of this client's latest action the server has processed, see [`crate::Prediction`]
+    pub ack: u64,
+    /// Hash of the server's seed when the queue is provably fair, see [`crate::fair`].
+    /// Only sent in full snapshots, as it never changes.
+    pub commitment: Option<[u8; 32]>,
+}
+
+impl<U: UserState, S: SharedState> Info<U, S> {
+    pub fn new(users: hashbrown::HashMap<u64, U::Info>, shared: S::Info, index: u64) -> Self {
+        Self {
+            users,
+            shared,
+            index,
+            session: 0,
+            spectators: 0,
+            ack: 0,
+            commitment: None,
+        }
+    }
+    /// Whether this info was generated for a spectator rather than a seated player
+    pub fn is_spectator(&self) -> bool {
+        !self.users.contains_key(&self.index)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(bound = "")]
+pub struct InfoDelta<U: UserState, S: SharedState> {
+    pub users: hashbrown::HashMap<u64, <U::Info as Diff>::Delta>,
+    pub added: hashbrown::HashMap<u64, U::Info>,
+    pub removed: Vec<u64>,
+    pub shared: Option<<S::Info as Diff>::Delta>,
+    pub spectators: u64,
+    pub ack: u64,
+}
+
+impl<U: UserState, S: SharedState> Diff for Info<U, S> {
+    type Delta = InfoDelta<U, S>;
+    fn diff(&self, old: &Self) -> Option<Self::Delta> {
+        let mut users = hashbrown::HashMap::new();
+        let mut added = hashbrown::HashMap::new();
+        for (i, user) in self.users.iter() {
+            match old.users.get(i) {
+                Some(old) => {
+                    if let Some(delta) = user.diff(old) {
+                        users.insert(*i, delta);
+                    }
+                }
+                None => {
+                    added.insert(*i, user.clone());
+                }
+            }
+        }
+        let removed: Vec<u64> = old
+            .users
+            .keys()
+            .filter(|i| !self.users.contains_key(*i))
+            .copied()
+            .collect();
+        let shared = self.shared.diff(&old.shared);
+        let unchanged = users.is_empty()
+            && added.is_empty()
+            && removed.is_empty()
+            && shared.is_none()
+            && self.spectators == old.spectators
+            && self.ack == old.ack;
+        (!unchanged).then_some(InfoDelta {
+            users,
+            added,
+            removed,
+            shared,
+            spectators: self.spectators,
+            ack: self.ack,
+        })
+    }
+    fn apply(&mut self, delta: Self::Delta) {
+        let InfoDelta {
+            users,
+            added,
+            removed,
+            shared,
+            spectators,
+            ack,
+        } = delta;
+        for (i, delta) in users {
+            if let Some(user) = self.users.get_mut(&i) {
+                user.apply(delta);
+            }
+        }
+        self.users.extend(added);
+        for i in removed {
+            self.users.remove(&i);
+        }
+        if let Some(delta) = shared {
+            self.shared.apply(delta);
+        }
+        self.spectators = spectators;
+        self.ack = ack;
+    }
 }
@@ -0,0 +1,38 @@
+use core::{fmt, str::FromStr};
+use serde::{Deserialize, Serialize};
+
+/// Characters used in invite codes, without easily confused ones like `0`/`O` and `1`/`I`.
+pub const INVITE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+pub const INVITE_LEN: usize = 6;
+
+/// Short code a host shares so others can join their private lobby.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InviteCode(pub [u8; INVITE_LEN]);
+
+impl InviteCode {
+    /// Build a code from random bytes
+    pub fn from_random(bytes: [u8; INVITE_LEN]) -> Self {
+        Self(bytes.map(|b| INVITE_ALPHABET[(b % 32) as usize]))
+    }
+}
+
+impl fmt::Display for InviteCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{}", *b as char))
+    }
+}
+
+impl FromStr for InviteCode {
+    type Err = eyre::Report;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_ascii_uppercase();
+        let code: [u8; INVITE_LEN] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| eyre::eyre!("Invite codes are {INVITE_LEN} characters long"))?;
+        if let Some(c) = code.iter().find(|c| !INVITE_ALPHABET.contains(c)) {
+            eyre::bail!("Invalid character {:?} in invite code", *c as char);
+        }
+        Ok(Self(code))
+    }
+}
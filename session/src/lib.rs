@@ -1,17 +1,35 @@
 #![feature(trait_alias)]
 pub mod action;
 pub mod codec;
+pub mod context;
+pub mod diff;
+pub mod fair;
 pub mod info;
+pub mod invite;
 pub mod msg;
+pub mod predict;
+pub mod protocol;
 pub mod queue;
+pub mod replay;
+pub mod schema;
+pub mod settings;
 pub mod state;
+pub mod stream;
 pub mod token;
 
 pub use action::*;
 pub use codec::*;
+pub use context::*;
+pub use diff::*;
+pub use fair::*;
 pub use hashbrown::HashMap;
 pub use info::*;
+pub use invite::*;
 pub use msg::*;
+pub use predict::*;
 pub use queue::*;
+pub use replay::*;
+pub use schema::*;
+pub use settings::*;
 pub use state::*;
 pub use token::*;
@@ -1,10 +1,10 @@
-use crate::{AsQueue, ClientToken};
+use crate::{AsQueue, ClientToken, Entropy, InviteCode, SettingsOf};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::fmt::Debug;
 
 pub trait Message = 'static + Clone + Send + Sync + Serialize + DeserializeOwned + Debug;
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(bound = "Q: Serialize + DeserializeOwned")]
 pub struct Msg<Q: AsQueue> {
     pub token: ClientToken,
@@ -22,7 +22,16 @@ impl<Q: AsQueue> Msg<Q> {
         }
     }
     pub fn accept(token: ClientToken, queue: Q) -> Self {
-        let msg_type = MsgType::Accept;
+        let msg_type = MsgType::Accept(None);
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    /// Accept and contribute `entropy` to the session seed, see [`crate::fair`]
+    pub fn accept_with(token: ClientToken, queue: Q, entropy: Entropy) -> Self {
+        let msg_type = MsgType::Accept(Some(entropy));
         Self {
             token,
             queue,
@@ -37,13 +46,86 @@ impl<Q: AsQueue> Msg<Q> {
             msg_type,
         }
     }
+    pub fn create(token: ClientToken, queue: Q) -> Self {
+        let msg_type = MsgType::Create;
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    pub fn join_code(token: ClientToken, queue: Q, code: InviteCode) -> Self {
+        let msg_type = MsgType::JoinCode(code);
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    pub fn start(token: ClientToken, queue: Q) -> Self {
+        let msg_type = MsgType::Start;
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    pub fn vote(token: ClientToken, queue: Q, settings: SettingsOf<Q::Action>) -> Self {
+        let msg_type = MsgType::Vote(settings);
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    pub fn spectate(token: ClientToken, queue: Q, session: u64) -> Self {
+        let msg_type = MsgType::Spectate(session);
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    pub fn reconnect(token: ClientToken, queue: Q) -> Self {
+        let msg_type = MsgType::Reconnect;
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
+    pub fn action(token: ClientToken, queue: Q, action: Q::Action, id: u64) -> Self {
+        let msg_type = MsgType::Action { action, id };
+        Self {
+            token,
+            queue,
+            msg_type,
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
 pub enum MsgType<Q: AsQueue> {
     Join,
     Reconnect,
-    Accept,
+    /// Accept the lobby, optionally contributing entropy to the session seed
+    Accept(Option<Entropy>),
+    /// Pick lobby settings, only possible before accepting
+    Vote(SettingsOf<Q::Action>),
     Leave,
-    Action(Q::Action),
+    /// Host a private lobby and receive an invite code
+    Create,
+    /// Join the private lobby with the given invite code
+    JoinCode(InviteCode),
+    /// Start the private lobby, sent by its host once it is full
+    Start,
+    /// Observe the session with the given id, see [`crate::Info::session`]
+    Spectate(u64),
+    /// An action with a client-chosen id, increasing on each connection, which is
+    /// answered with an [`crate::protocol::ActionReply`] and echoed in [`crate::Info::ack`]
+    Action {
+        action: Q::Action,
+        id: u64,
+    },
 }
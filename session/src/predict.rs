@@ -0,0 +1,331 @@
+use crate::*;
+use core::time::Duration;
+use hashbrown::HashMap;
+use rand_chacha::rand_core::SeedableRng;
+use std::{borrow::Borrow, collections::VecDeque};
+
+/// Opt-in client-side prediction: rebuild full states from what a client can see,
+/// so it can resolve its own actions before the server confirms them.
+pub trait Predict: Action {
+    fn shared(info: &<Self::Shared as SharedState>::Info) -> Self::Shared;
+    /// Rebuild user `index`, whose private fields are only known for the client's own user
+    fn user(index: u64, info: &<Self::User as UserState>::Info) -> Self::User;
+}
+
+/// Client-side state built from the last [`Info`], indexed like [`Info::users`]
+#[derive(Clone, Debug)]
+pub struct PredictState<A: Action> {
+    users: HashMap<u64, A::User>,
+    shared: A::Shared,
+}
+
+impl<A: Predict> PredictState<A> {
+    pub fn new(info: &Info<A::User, A::Shared>) -> Self {
+        Self {
+            users: info
+                .users
+                .iter()
+                .map(|(i, user)| (*i, A::user(*i, user)))
+                .collect(),
+            shared: A::shared(&info.shared),
+        }
+    }
+    /// Info as seen by user `index`, with session metadata copied from `base`
+    pub fn info(&self, base: &Info<A::User, A::Shared>) -> Info<A::User, A::Shared> {
+        let mut info = Info::new(
+            A::User::info(base.index, self),
+            A::Shared::info(base.index, self),
+            base.index,
+        );
+        info.session = base.session;
+        info.spectators = base.spectators;
+        info.ack = base.ack;
+        info.commitment = base.commitment;
+        info
+    }
+    /// Resolve `action` for user `index`, leaving the state untouched if it is rejected.
+    /// The server's random numbers cannot be known ahead of time, so the context gets a
+    /// fixed seed and its events are discarded.
+    fn resolve(&mut self, index: u64, action: A) {
+        let before = self.clone();
+        let mut rng = SessionRng::from_seed([0; 32]);
+        let mut events = Vec::new();
+        let mut ctx = Context::new(Duration::ZERO, Duration::ZERO, &mut rng, &mut events);
+        if action.resolve(index, &mut *self, &mut ctx).is_err() {
+            *self = before;
+        }
+    }
+}
+
+impl<A: Action> AsState for PredictState<A> {
+    type Shared = A::Shared;
+    type User = A::User;
+    type Index = u64;
+    fn index_matches(&self, i: u64, index: Self::Index) -> bool {
+        i == index
+    }
+    fn user(&self, i: u64) -> Option<impl Borrow<Self::User>> {
+        self.users.get(&i)
+    }
+    fn users(&self) -> impl Iterator<Item = (u64, impl Borrow<Self::User>)> {
+        self.users.iter().map(|(i, user)| (*i, user))
+    }
+    fn user_mut(&mut self, i: u64) -> Option<impl AsMut<Self::User>> {
+        self.users.get_mut(&i).map(Mutable)
+    }
+    fn shared(&self) -> impl Borrow<Self::Shared> {
+        &self.shared
+    }
+    fn shared_mut(&mut self) -> Option<impl AsMut<Self::Shared>> {
+        Some(Mutable(&mut self.shared))
+    }
+    fn indices(&self) -> impl Iterator<Item = u64> {
+        self.users.keys().copied()
+    }
+}
+
+pub(crate) struct Mutable<'a, T>(pub(crate) &'a mut T);
+
+impl<T> AsMut<T> for Mutable<'_, T> {
+    fn as_mut(&mut self) -> &mut T {
+        self.0
+    }
+}
+
+/// Actions resolved locally on top of the latest authoritative session info.
+/// Once the server's [`Info::ack`] reaches an action's id the action is dropped,
+/// and the rest are replayed on the new info.
+#[derive(Clone, Debug)]
+pub struct Prediction<A: Action> {
+    confirmed: Option<Info<A::User, A::Shared>>,
+    state: Option<PredictState<A>>,
+    predicted: Option<Info<A::User, A::Shared>>,
+    pending: VecDeque<(u64, A)>,
+}
+
+impl<A: Action> Default for Prediction<A> {
+    fn default() -> Self {
+        Self {
+            confirmed: None,
+            state: None,
+            predicted: None,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<A: Predict> Prediction<A> {
+    /// Latest info with pending actions applied, `None` outside of a session
+    pub fn info(&self) -> Option<&Info<A::User, A::Shared>> {
+        self.predicted.as_ref()
+    }
+    /// Actions not yet acknowledged by the server
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+    /// Resolve `action` locally, for the action sent to the server with id `id`.
+    /// Ids must increase with every action sent on the connection.
+    pub fn act(&mut self, id: u64, action: A) {
+        self.pending.push_back((id, action));
+        if let (Some(confirmed), Some(state)) = (&self.confirmed, &mut self.state) {
+            state.resolve(confirmed.index, action);
+            self.predicted = Some(state.info(confirmed));
+        }
+    }
+    /// Reconcile with authoritative state from the server, replaying whatever it
+    /// has not processed yet
+    pub fn confirm(&mut self, state: &StateInfo<A>) {
+        let StateInfo::Session(info) = state else {
+            self.confirmed = None;
+            self.state = None;
+            self.predicted = None;
+            self.pending.clear();
+            return;
+        };
+        self.pending.retain(|(id, _)| *id > info.ack);
+        let mut state = PredictState::new(info);
+        for (_, action) in self.pending.iter() {
+            // a rejected action is left out of the prediction, the server will reject it too
+            state.resolve(info.index, *action);
+        }
+        self.predicted = Some(state.info(info));
+        self.state = Some(state);
+        self.confirmed = Some(info.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+    struct Score(u32);
+
+    impl Schema for Score {
+        const FINGERPRINT: u64 = fingerprint(b"Score");
+    }
+
+    impl Diff for Score {
+        type Delta = u32;
+        fn diff(&self, old: &Self) -> Option<u32> {
+            (self != old).then_some(self.0)
+        }
+        fn apply(&mut self, delta: u32) {
+            self.0 = delta;
+        }
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Board {
+        total: u32,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    struct Player {
+        score: u32,
+    }
+
+    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    struct Add(u32);
+
+    impl Schema for Add {
+        const FINGERPRINT: u64 = fingerprint(b"Add");
+    }
+
+    impl SharedState for Board {
+        type Info = Score;
+        type User = Player;
+        type Settings = ();
+        fn info<S: AsState<Shared = Self>>(_index: S::Index, state: &S) -> Score {
+            Score(state.shared().borrow().total)
+        }
+        fn init(_seed: [u8; 32], _settings: ()) -> Self {
+            Self::default()
+        }
+    }
+
+    impl UserState for Player {
+        type Info = Score;
+        type Shared = Board;
+        fn info<S: AsState<User = Self>>(_index: S::Index, state: &S) -> HashMap<u64, Score> {
+            state
+                .users()
+                .map(|(i, u)| (i, Score(u.borrow().score)))
+                .collect()
+        }
+        fn init(_shared: &mut Board, users: usize) -> Vec<Self> {
+            vec![Self::default(); users]
+        }
+    }
+
+    impl Action for Add {
+        type Shared = Board;
+        type User = Player;
+        type Error = ();
+        type Event = ();
+        fn update<S: AsState<User = Player, Shared = Board>>(
+            _state: S,
+            _ctx: &mut Context<'_, Self>,
+        ) {
+        }
+        fn resolve<S: AsState<User = Player, Shared = Board>>(
+            self,
+            index: u64,
+            mut state: S,
+            _ctx: &mut Context<'_, Self>,
+        ) -> Result<(), ()> {
+            if self.0 == 0 {
+                // changes before the rejection must not leak into the prediction
+                state.shared_mut().ok_or(())?.as_mut().total += 100;
+                return Err(());
+            }
+            state.user_mut(index).ok_or(())?.as_mut().score += self.0;
+            state.shared_mut().ok_or(())?.as_mut().total += self.0;
+            Ok(())
+        }
+    }
+
+    impl Predict for Add {
+        fn shared(info: &Score) -> Board {
+            Board { total: info.0 }
+        }
+        fn user(_index: u64, info: &Score) -> Player {
+            Player { score: info.0 }
+        }
+    }
+
+    /// Session snapshot for seat 1 of 2, after the server processed action `ack`
+    fn session(score: u32, total: u32, ack: u64) -> StateInfo<Add> {
+        let mut info = Info::new(
+            [(1, Score(score)), (2, Score(total - score))].into(),
+            Score(total),
+            1,
+        );
+        info.session = 7;
+        info.ack = ack;
+        StateInfo::Session(info)
+    }
+
+    fn total(prediction: &Prediction<Add>) -> u32 {
+        prediction.info().expect("in a session").shared.0
+    }
+
+    #[test]
+    fn acks() {
+        let mut prediction = Prediction::default();
+        prediction.confirm(&session(0, 4, 0));
+        prediction.act(1, Add(2));
+        prediction.act(2, Add(3));
+        prediction.act(3, Add(5));
+        assert_eq!((prediction.pending(), total(&prediction)), (3, 14));
+        // several actions acknowledged by one snapshot, the rest replayed on top
+        prediction.confirm(&session(5, 9, 2));
+        assert_eq!((prediction.pending(), total(&prediction)), (1, 14));
+        assert_eq!(prediction.info().unwrap().users[&1], Score(10));
+        // an older ack arriving late doesn't bring back acknowledged actions
+        prediction.confirm(&session(2, 6, 1));
+        assert_eq!((prediction.pending(), total(&prediction)), (1, 11));
+        prediction.confirm(&session(10, 14, 3));
+        assert_eq!((prediction.pending(), total(&prediction)), (0, 14));
+        assert_eq!(prediction.info().unwrap().session, 7);
+    }
+
+    #[test]
+    fn rejected() {
+        let mut prediction = Prediction::default();
+        prediction.confirm(&session(0, 4, 0));
+        prediction.act(1, Add(0));
+        assert_eq!((prediction.pending(), total(&prediction)), (1, 4));
+        prediction.act(2, Add(3));
+        assert_eq!((prediction.pending(), total(&prediction)), (2, 7));
+        // the server rejected 1 without changing anything, 2 is still in flight
+        prediction.confirm(&session(0, 4, 1));
+        assert_eq!((prediction.pending(), total(&prediction)), (1, 7));
+        prediction.confirm(&session(3, 7, 2));
+        assert_eq!((prediction.pending(), total(&prediction)), (0, 7));
+    }
+
+    #[test]
+    fn reconnect() {
+        let mut prediction = Prediction::default();
+        // actions taken outside of a session are kept but not predicted
+        prediction.act(1, Add(2));
+        assert!(prediction.info().is_none());
+        prediction.confirm(&session(0, 4, 0));
+        assert_eq!((prediction.pending(), total(&prediction)), (1, 6));
+        prediction.confirm(&session(2, 6, 1));
+        // sent while disconnected, so only resent once the connection is back
+        prediction.act(2, Add(3));
+        prediction.confirm(&session(2, 6, 1));
+        assert_eq!((prediction.pending(), total(&prediction)), (1, 9));
+        assert_eq!(prediction.info().unwrap().users[&1], Score(5));
+        prediction.confirm(&session(5, 9, 2));
+        assert_eq!((prediction.pending(), total(&prediction)), (0, 9));
+        // leaving the session drops whatever was pending
+        prediction.act(3, Add(1));
+        prediction.confirm(&StateInfo::Closed);
+        assert_eq!(prediction.pending(), 0);
+        assert!(prediction.info().is_none());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::*;
+use core::fmt;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// Bumped whenever the envelope or any framed message changes shape
+pub const PROTOCOL_VERSION: u16 = 5;
+
+/// Close code sent when a peer speaks an incompatible protocol
+pub const PROTOCOL_CLOSE_CODE: u16 = 1002;
+
+/// Close code sent to banned clients (policy violation), with the ban reason. Clients
+/// treat it as final rather than reconnecting.
+pub const BAN_CLOSE_CODE: u16 = 1008;
+
+/// Length of the envelope header: version (u16 LE) then kind (u8)
+pub const HEADER_LEN: usize = 3;
+
+/// What an envelope's payload contains
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Kind {
+    /// Client to server, first message on every connection
+    Hello = 0,
+    /// Server to client, the handshake succeeded
+    Welcome = 1,
+    /// Server to client, the connection is refused with a readable reason
+    Reject = 2,
+    /// Client to server, a [`Msg`]
+    Msg = 3,
+    /// Server to client, a [`StateInfo`]
+    State = 4,
+    /// Server to client, an [`ActionReply`]
+    Reply = 5,
+    /// Server to client, [`Events`]
+    Events = 6,
+}
+
+impl TryFrom<u8> for Kind {
+    type Error = ProtocolError;
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Hello,
+            1 => Self::Welcome,
+            2 => Self::Reject,
+            3 => Self::Msg,
+            4 => Self::State,
+            5 => Self::Reply,
+            6 => Self::Events,
+            kind => return Err(ProtocolError::UnknownKind(kind)),
+        })
+    }
+}
+
+/// A message that can be sent inside an envelope
+pub trait Framed: Serialize + DeserializeOwned {
+    const KIND: Kind;
+}
+
+/// Opens the handshake, carrying the client's [`AsQueue::FINGERPRINT`]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hello {
+    pub fingerprint: u64,
+}
+
+impl Hello {
+    pub fn new<Q: AsQueue>() -> Self {
+        Self {
+            fingerprint: Q::FINGERPRINT,
+        }
+    }
+}
+
+/// Accepts the handshake, carrying the server's [`AsQueue::FINGERPRINT`]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Welcome {
+    pub fingerprint: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reject(pub String);
+
+/// Outcome of an action, sent only to the player who took it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct ActionReply<A: Action> {
+    /// Id the client sent the action with
+    pub id: u64,
+    pub result: Result<(), A::Error>,
+}
+
+/// Transient events emitted during a tick, see [`crate::Context::emit`]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct Events<A: Action>(pub Vec<A::Event>);
+
+impl Framed for Hello {
+    const KIND: Kind = Kind::Hello;
+}
+
+impl Framed for Welcome {
+    const KIND: Kind = Kind::Welcome;
+}
+
+impl Framed for Reject {
+    const KIND: Kind = Kind::Reject;
+}
+
+impl<Q: AsQueue + Serialize + DeserializeOwned> Framed for Msg<Q> {
+    const KIND: Kind = Kind::Msg;
+}
+
+impl<A: Action> Framed for StateInfo<A> {
+    const KIND: Kind = Kind::State;
+}
+
+impl<A: Action> Framed for ActionReply<A> {
+    const KIND: Kind = Kind::Reply;
+}
+
+impl<A: Action> Framed for Events<A> {
+    const KIND: Kind = Kind::Events;
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    Truncated,
+    Version { ours: u16, theirs: u16 },
+    UnknownKind(u8),
+    UnexpectedKind { expected: Kind, found: Kind },
+    Payload(bitcode::Error),
+    UnknownFrame(u8),
+    TooLarge(usize),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "message shorter than the protocol header"),
+            Self::Version { ours, theirs } => write!(
+                f,
+                "protocol version mismatch: this end speaks v{ours}, the other end speaks v{theirs}"
+            ),
+            Self::UnknownKind(kind) => write!(f, "unknown message kind {kind}"),
+            Self::UnexpectedKind { expected, found } => {
+                write!(f, "expected a {expected:?} message, got {found:?}")
+            }
+            Self::Payload(e) => write!(f, "malformed payload: {e}"),
+            Self::UnknownFrame(tag) => write!(f, "unknown stream frame tag {tag}"),
+            Self::TooLarge(len) => write!(f, "stream frame of {len} bytes exceeds the limit"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+/// A decoded envelope whose payload has not been interpreted yet
+#[derive(Clone, Debug)]
+pub struct Envelope<'a> {
+    pub version: u16,
+    pub kind: Kind,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    /// Read the header, refusing envelopes from another protocol version
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ProtocolError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(ProtocolError::Truncated);
+        }
+        let version = u16::from_le_bytes([bytes[0], bytes[1]]);
+        if version != PROTOCOL_VERSION {
+            return Err(ProtocolError::Version {
+                ours: PROTOCOL_VERSION,
+                theirs: version,
+            });
+        }
+        let kind = Kind::try_from(bytes[2])?;
+        let payload = &bytes[HEADER_LEN..];
+        Ok(Self {
+            version,
+            kind,
+            payload,
+        })
+    }
+    pub fn decode<T: Framed>(&self) -> Result<T, ProtocolError> {
+        if self.kind != T::KIND {
+            return Err(ProtocolError::UnexpectedKind {
+                expected: T::KIND,
+                found: self.kind,
+            });
+        }
+        bitcode::deserialize(self.payload).map_err(ProtocolError::Payload)
+    }
+}
+
+pub fn encode<T: Framed>(val: &T) -> Result<Vec<u8>, ProtocolError> {
+    let payload = bitcode::serialize(val).map_err(ProtocolError::Payload)?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+    bytes.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    bytes.push(T::KIND as u8);
+    bytes.extend_from_slice(&payload);
+    Ok(bytes)
+}
+
+pub fn decode<T: Framed>(bytes: &[u8]) -> Result<T, ProtocolError> {
+    Envelope::parse(bytes)?.decode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello() -> Vec<u8> {
+        encode(&Hello { fingerprint: 42 }).unwrap()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let bytes = hello();
+        let envelope = Envelope::parse(&bytes).unwrap();
+        assert_eq!(envelope.version, PROTOCOL_VERSION);
+        assert_eq!(envelope.kind, Kind::Hello);
+        assert_eq!(envelope.decode::<Hello>().unwrap().fingerprint, 42);
+    }
+
+    #[test]
+    fn truncated() {
+        assert!(matches!(
+            Envelope::parse(&[]),
+            Err(ProtocolError::Truncated)
+        ));
+        let bytes = hello();
+        assert!(matches!(
+            Envelope::parse(&bytes[..HEADER_LEN - 1]),
+            Err(ProtocolError::Truncated)
+        ));
+        // a header with an empty payload is still an envelope
+        assert!(Envelope::parse(&bytes[..HEADER_LEN]).is_ok());
+    }
+
+    #[test]
+    fn version() {
+        let mut bytes = hello();
+        bytes[..2].copy_from_slice(&(PROTOCOL_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            Envelope::parse(&bytes),
+            Err(ProtocolError::Version { ours, theirs })
+                if ours == PROTOCOL_VERSION && theirs == PROTOCOL_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn kind() {
+        let mut bytes = hello();
+        bytes[2] = 200;
+        assert!(matches!(
+            Envelope::parse(&bytes),
+            Err(ProtocolError::UnknownKind(200))
+        ));
+        bytes[2] = Kind::Reject as u8;
+        assert!(matches!(
+            Envelope::parse(&bytes).unwrap().decode::<Hello>(),
+            Err(ProtocolError::UnexpectedKind {
+                expected: Kind::Hello,
+                found: Kind::Reject,
+            })
+        ));
+    }
+
+    #[test]
+    fn payload() {
+        let mut bytes = hello();
+        bytes.truncate(HEADER_LEN);
+        assert!(matches!(
+            decode::<Hello>(&bytes),
+            Err(ProtocolError::Payload(_))
+        ));
+    }
+}
@@ -3,4 +3,6 @@ use crate::Action;
 /// Everything that implements AsQueue on the client should implement Queue on the server
 pub trait AsQueue: Send + Sync + 'static {
     type Action: Action;
+    /// Fingerprint of the queue, its action, info and settings types, see [`crate::Schema`]
+    const FINGERPRINT: u64;
 }
@@ -0,0 +1,412 @@
+use crate::{predict::Mutable, *};
+use core::{fmt, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::borrow::Borrow;
+
+/// Bytes every replay file starts with
+const REPLAY_MAGIC: [u8; 4] = *b"ilrp";
+pub const REPLAY_VERSION: u16 = 2;
+const REPLAY_HEADER_LEN: usize = REPLAY_MAGIC.len() + 2;
+
+/// An action as it reached [`Action::resolve`], whether or not it was rejected
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(bound = "")]
+pub struct Recorded<A: Action> {
+    /// Index into [`Replay::ticks`] of the tick the action was resolved in
+    pub tick: u64,
+    /// Seat of the player who took the action
+    pub index: u64,
+    pub action: A,
+}
+
+/// Everything needed to re-run a session: how it was initialized, every action
+/// resolved in it and every tick it was updated on.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(bound = "")]
+pub struct Replay<A: Action> {
+    /// Seed the session's shared state and [`SessionRng`] were drawn from
+    pub seed: [u8; 32],
+    pub settings: SettingsOf<A>,
+    /// Seat indices in lobby order
+    pub seats: Vec<u64>,
+    /// Server time when the session's first tick began, see [`crate::Context::elapsed`]
+    pub start: Duration,
+    /// Length of each server tick, at the end of which [`Action::update`] ran
+    pub ticks: Vec<Duration>,
+    pub actions: Vec<Recorded<A>>,
+    /// [`checksum`] of the state the session ended in, `None` if it never ended
+    pub checksum: Option<u64>,
+}
+
+impl<A: Action> Replay<A> {
+    pub fn new(seed: [u8; 32], settings: SettingsOf<A>, seats: Vec<u64>) -> Self {
+        Self {
+            seed,
+            settings,
+            seats,
+            start: Duration::ZERO,
+            ticks: Vec::new(),
+            actions: Vec::new(),
+            checksum: None,
+        }
+    }
+    /// Record an action resolved in the current tick
+    pub fn action(&mut self, index: u64, action: A) {
+        let tick = self.ticks.len() as u64;
+        self.actions.push(Recorded {
+            tick,
+            index,
+            action,
+        });
+    }
+    /// Record the end of the current tick
+    pub fn tick(&mut self, delta: Duration, elapsed: Duration) {
+        if self.ticks.is_empty() {
+            self.start = elapsed.saturating_sub(delta);
+        }
+        self.ticks.push(delta);
+    }
+    /// Server time at the end of tick `tick`, as actions resolved in it saw it
+    pub fn elapsed(&self, tick: usize) -> Duration {
+        self.start + self.ticks.iter().take(tick + 1).sum::<Duration>()
+    }
+    /// Time from the session's first tick to its last
+    pub fn duration(&self) -> Duration {
+        self.ticks.iter().sum()
+    }
+    /// Record the state the session ended in
+    pub fn finish<S: AsState<Shared = A::Shared, User = A::User>>(&mut self, state: &S)
+    where
+        A::Shared: Checksum,
+        A::User: Checksum,
+    {
+        self.checksum = Some(checksum(state));
+    }
+    /// Encode for a replay file, prefixed with a header naming the format version
+    pub fn encode(&self) -> Result<Vec<u8>, ReplayError> {
+        let payload = bitcode::serialize(&(A::FINGERPRINT, self)).map_err(ReplayError::Payload)?;
+        let mut bytes = Vec::with_capacity(REPLAY_HEADER_LEN + payload.len());
+        bytes.extend_from_slice(&REPLAY_MAGIC);
+        bytes.extend_from_slice(&REPLAY_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+    /// Decode a replay file, refusing replays of another format version or game
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        if bytes.len() < REPLAY_HEADER_LEN || bytes[..REPLAY_MAGIC.len()] != REPLAY_MAGIC {
+            return Err(ReplayError::NotAReplay);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::Version {
+                ours: REPLAY_VERSION,
+                theirs: version,
+            });
+        }
+        let (fingerprint, replay): (u64, Self) =
+            bitcode::deserialize(&bytes[REPLAY_HEADER_LEN..]).map_err(ReplayError::Payload)?;
+        if fingerprint != A::FINGERPRINT {
+            return Err(ReplayError::Schema {
+                ours: A::FINGERPRINT,
+                theirs: fingerprint,
+            });
+        }
+        Ok(replay)
+    }
+}
+
+impl<A: Action> Default for Replay<A> {
+    fn default() -> Self {
+        Self::new([0; 32], Default::default(), Vec::new())
+    }
+}
+
+/// Hash of a state's full content, private fields included, which a replayed session
+/// is compared with the original by. It must come out the same in every run, so it
+/// cannot depend on e.g. the iteration order of a `HashMap`.
+pub trait Checksum {
+    fn checksum(&self) -> u64;
+}
+
+/// Hash of `value`'s bitcode encoding, for implementing [`Checksum`] on serializable
+/// state. Only stable if every map and set in it is ordered, like `BTreeMap`.
+pub fn checksum_of<T: Serialize + ?Sized>(value: &T) -> u64 {
+    // encoding state that fits in memory cannot fail
+    fingerprint(&bitcode::serialize(value).unwrap_or_default())
+}
+
+/// Hash of a session's full state, for comparing a replayed session with the original.
+/// Users are hashed in the order of [`AsState::indices`].
+pub fn checksum<S: AsState>(state: &S) -> u64
+where
+    S::Shared: Checksum,
+    S::User: Checksum,
+{
+    let shared = state.shared();
+    let shared: &S::Shared = shared.borrow();
+    let mut hash = shared.checksum();
+    for i in state.indices() {
+        if let Some(user) = state.user(i) {
+            let user: &S::User = user.borrow();
+            hash = combine(combine(hash, i), user.checksum());
+        }
+    }
+    hash
+}
+
+/// A session rebuilt from a [`Replay`], with users in lobby order like on the server
+#[derive(Clone, Debug)]
+pub struct ReplayState<A: Action> {
+    users: Vec<(u64, A::User)>,
+    shared: A::Shared,
+}
+
+impl<A: Action> ReplayState<A>
+where
+    A::User: UserState<Shared = A::Shared>,
+{
+    /// The session as it started, before any action or tick
+    pub fn init(replay: &Replay<A>) -> Self {
+        let mut shared = A::Shared::init(replay.seed, replay.settings.clone());
+        let users = A::User::init(&mut shared, replay.seats.len());
+        Self {
+            users: replay.seats.iter().copied().zip(users).collect(),
+            shared,
+        }
+    }
+}
+
+impl<A: Action> AsState for ReplayState<A> {
+    type Shared = A::Shared;
+    type User = A::User;
+    type Index = u64;
+    fn index_matches(&self, i: u64, index: Self::Index) -> bool {
+        i == index
+    }
+    fn user(&self, i: u64) -> Option<impl Borrow<Self::User>> {
+        self.users
+            .iter()
+            .find(|(j, _)| *j == i)
+            .map(|(_, user)| user)
+    }
+    fn users(&self) -> impl Iterator<Item = (u64, impl Borrow<Self::User>)> {
+        self.users.iter().map(|(i, user)| (*i, user))
+    }
+    fn user_mut(&mut self, i: u64) -> Option<impl AsMut<Self::User>> {
+        self.users
+            .iter_mut()
+            .find(|(j, _)| *j == i)
+            .map(|(_, user)| Mutable(user))
+    }
+    fn shared(&self) -> impl Borrow<Self::Shared> {
+        &self.shared
+    }
+    fn shared_mut(&mut self) -> Option<impl AsMut<Self::Shared>> {
+        Some(Mutable(&mut self.shared))
+    }
+    fn indices(&self) -> impl Iterator<Item = u64> {
+        self.users.iter().map(|(i, _)| *i)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    NotAReplay,
+    Version {
+        ours: u16,
+        theirs: u16,
+    },
+    Schema {
+        ours: u64,
+        theirs: u64,
+    },
+    Payload(bitcode::Error),
+    /// The recorded session never ended, so there is no final state to compare
+    Unfinished,
+    Mismatch {
+        expected: u64,
+        found: u64,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAReplay => write!(f, "not a replay file"),
+            Self::Version { ours, theirs } => write!(
+                f,
+                "replay format mismatch: this build reads v{ours}, the file is v{theirs}"
+            ),
+            Self::Schema { ours, theirs } => write!(
+                f,
+                "replay of another game: action schema {theirs:016x} does not match {ours:016x}"
+            ),
+            Self::Payload(e) => write!(f, "malformed replay: {e}"),
+            Self::Unfinished => write!(f, "the recorded session never ended"),
+            Self::Mismatch { expected, found } => write!(
+                f,
+                "replay diverged: expected final state {expected:016x}, found {found:016x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashbrown::HashMap;
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+    struct Board {
+        total: u32,
+        // iterated in a different order every run, so sorted for the checksum
+        seen: HashMap<u64, u32>,
+    }
+
+    #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+    struct Player {
+        score: u32,
+    }
+
+    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    struct Add(u32);
+
+    impl Schema for Add {
+        const FINGERPRINT: u64 = fingerprint(b"Add");
+    }
+
+    impl Diff for u32 {
+        type Delta = u32;
+        fn diff(&self, old: &Self) -> Option<u32> {
+            (self != old).then_some(*self)
+        }
+        fn apply(&mut self, delta: u32) {
+            *self = delta;
+        }
+    }
+
+    impl SharedState for Board {
+        type Info = u32;
+        type User = Player;
+        type Settings = ();
+        fn info<S: AsState<Shared = Self>>(_index: S::Index, state: &S) -> u32 {
+            state.shared().borrow().total
+        }
+        fn init(_seed: [u8; 32], _settings: ()) -> Self {
+            Self::default()
+        }
+    }
+
+    impl UserState for Player {
+        type Info = u32;
+        type Shared = Board;
+        fn info<S: AsState<User = Self>>(_index: S::Index, state: &S) -> HashMap<u64, u32> {
+            state.users().map(|(i, u)| (i, u.borrow().score)).collect()
+        }
+        fn init(_shared: &mut Board, users: usize) -> Vec<Self> {
+            vec![Self::default(); users]
+        }
+    }
+
+    impl Checksum for Board {
+        fn checksum(&self) -> u64 {
+            let seen: BTreeMap<_, _> = self.seen.iter().collect();
+            checksum_of(&(self.total, seen))
+        }
+    }
+
+    impl Checksum for Player {
+        fn checksum(&self) -> u64 {
+            checksum_of(&self.score)
+        }
+    }
+
+    impl Action for Add {
+        type Shared = Board;
+        type User = Player;
+        type Error = ();
+        type Event = ();
+        fn update<S: AsState<User = Player, Shared = Board>>(
+            _state: S,
+            _ctx: &mut Context<'_, Self>,
+        ) {
+        }
+        fn resolve<S: AsState<User = Player, Shared = Board>>(
+            self,
+            index: u64,
+            mut state: S,
+            _ctx: &mut Context<'_, Self>,
+        ) -> Result<(), ()> {
+            state.user_mut(index).ok_or(())?.as_mut().score += self.0;
+            let mut shared = state.shared_mut().ok_or(())?;
+            shared.as_mut().total += self.0;
+            *shared.as_mut().seen.entry(index).or_default() += 1;
+            Ok(())
+        }
+    }
+
+    fn recorded() -> Replay<Add> {
+        let mut replay = Replay::new([3; 32], (), vec![4, 9]);
+        replay.action(4, Add(2));
+        replay.tick(Duration::from_millis(16), Duration::from_millis(116));
+        replay.action(9, Add(5));
+        replay.action(4, Add(1));
+        replay.tick(Duration::from_millis(17), Duration::from_millis(133));
+        replay.finish(&ReplayState::init(&replay));
+        replay
+    }
+
+    #[test]
+    fn roundtrip() {
+        let replay = recorded();
+        let decoded = Replay::<Add>::decode(&replay.encode().unwrap()).unwrap();
+        assert_eq!(decoded.seed, replay.seed);
+        assert_eq!(decoded.seats, replay.seats);
+        assert_eq!(decoded.ticks, replay.ticks);
+        assert_eq!(decoded.actions.len(), 3);
+        assert_eq!(decoded.actions[1].tick, 1);
+        assert_eq!(decoded.start, Duration::from_millis(100));
+        assert_eq!(decoded.elapsed(0), Duration::from_millis(116));
+        assert_eq!(decoded.elapsed(1), Duration::from_millis(133));
+        assert_eq!(decoded.duration(), Duration::from_millis(33));
+        assert_eq!(decoded.checksum, replay.checksum);
+    }
+
+    #[test]
+    fn checksums() {
+        let replay = recorded();
+        let mut a = ReplayState::init(&replay);
+        let mut b = ReplayState::init(&replay);
+        // the same content inserted in another order hashes the same
+        for (state, order) in [(&mut a, [1, 2, 3]), (&mut b, [3, 2, 1])] {
+            let mut shared = state.shared_mut().unwrap();
+            for i in order {
+                shared.as_mut().seen.insert(i, i as u32);
+            }
+        }
+        assert_eq!(checksum(&a), checksum(&b));
+        b.user_mut(9).unwrap().as_mut().score += 1;
+        assert_ne!(checksum(&a), checksum(&b));
+    }
+
+    #[test]
+    fn rejects() {
+        let bytes = recorded().encode().unwrap();
+        assert!(matches!(
+            Replay::<Add>::decode(b"nope"),
+            Err(ReplayError::NotAReplay)
+        ));
+        let mut other = bytes.clone();
+        other[4] = 0;
+        assert!(matches!(
+            Replay::<Add>::decode(&other),
+            Err(ReplayError::Version { .. })
+        ));
+        let mut other = bytes.clone();
+        other.truncate(other.len() - 1);
+        assert!(Replay::<Add>::decode(&other).is_err());
+    }
+}
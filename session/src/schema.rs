@@ -0,0 +1,191 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Stable fingerprint of a type's wire shape, compared at connection time so clients
+/// built against another revision of the game are refused instead of decoding garbage.
+///
+/// Derived fingerprints cover the declared names of the deriving type and fold in the
+/// fingerprint of every field type, so each field type must implement `Schema` too.
+/// Types containing themselves can't be fingerprinted.
+pub trait Schema {
+    const FINGERPRINT: u64;
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// FNV-1a hash of `bytes`
+pub const fn fingerprint(bytes: &[u8]) -> u64 {
+    let mut hash = FNV_OFFSET;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+/// Order-dependent combination of two fingerprints
+pub const fn combine(a: u64, b: u64) -> u64 {
+    let mut hash = a;
+    let b = b.to_le_bytes();
+    let mut i = 0;
+    while i < b.len() {
+        hash ^= b[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash
+}
+
+macro_rules! schema_by_name {
+    ($($ty:ty),*) => {
+        $(impl Schema for $ty {
+            const FINGERPRINT: u64 = fingerprint(stringify!($ty).as_bytes());
+        })*
+    };
+}
+
+schema_by_name!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    String
+);
+
+impl<T: Schema> Schema for Option<T> {
+    const FINGERPRINT: u64 = combine(fingerprint(b"Option"), T::FINGERPRINT);
+}
+
+impl<T: Schema> Schema for Vec<T> {
+    const FINGERPRINT: u64 = combine(fingerprint(b"Vec"), T::FINGERPRINT);
+}
+
+impl<T: Schema, const N: usize> Schema for [T; N] {
+    const FINGERPRINT: u64 = combine(combine(fingerprint(b"array"), T::FINGERPRINT), N as u64);
+}
+
+impl<T: Schema> Schema for VecDeque<T> {
+    const FINGERPRINT: u64 = combine(fingerprint(b"VecDeque"), T::FINGERPRINT);
+}
+
+impl<T: Schema, S> Schema for HashSet<T, S> {
+    const FINGERPRINT: u64 = combine(fingerprint(b"HashSet"), T::FINGERPRINT);
+}
+
+impl<T: Schema> Schema for BTreeSet<T> {
+    const FINGERPRINT: u64 = combine(fingerprint(b"BTreeSet"), T::FINGERPRINT);
+}
+
+impl<K: Schema, V: Schema, S> Schema for HashMap<K, V, S> {
+    const FINGERPRINT: u64 = combine(
+        combine(fingerprint(b"HashMap"), K::FINGERPRINT),
+        V::FINGERPRINT,
+    );
+}
+
+impl<K: Schema, V: Schema> Schema for BTreeMap<K, V> {
+    const FINGERPRINT: u64 = combine(
+        combine(fingerprint(b"BTreeMap"), K::FINGERPRINT),
+        V::FINGERPRINT,
+    );
+}
+
+// unsized and borrowed forms go over the wire exactly like their owned counterparts
+impl Schema for str {
+    const FINGERPRINT: u64 = String::FINGERPRINT;
+}
+
+impl<T: Schema> Schema for [T] {
+    const FINGERPRINT: u64 = Vec::<T>::FINGERPRINT;
+}
+
+// pointers are transparent on the wire, so they share the fingerprint of what they hold
+macro_rules! schema_transparent {
+    ($($ptr:ident),*) => {
+        $(impl<T: Schema + ?Sized> Schema for $ptr<T> {
+            const FINGERPRINT: u64 = T::FINGERPRINT;
+        })*
+    };
+}
+
+schema_transparent!(Box, Rc, Arc);
+
+impl<T: Schema + ToOwned + ?Sized> Schema for Cow<'_, T> {
+    const FINGERPRINT: u64 = T::FINGERPRINT;
+}
+
+macro_rules! schema_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: Schema),+> Schema for ($($name,)+) {
+            const FINGERPRINT: u64 = {
+                let hash = fingerprint(b"tuple");
+                $(let hash = combine(hash, $name::FINGERPRINT);)+
+                hash
+            };
+        }
+    };
+}
+
+schema_tuple!(A);
+schema_tuple!(A B);
+schema_tuple!(A B C);
+schema_tuple!(A B C D);
+schema_tuple!(A B C D E);
+schema_tuple!(A B C D E F);
+schema_tuple!(A B C D E F G);
+schema_tuple!(A B C D E F G H);
+schema_tuple!(A B C D E F G H I);
+schema_tuple!(A B C D E F G H I J);
+schema_tuple!(A B C D E F G H I J K);
+schema_tuple!(A B C D E F G H I J K L);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        assert_ne!(
+            HashMap::<u32, String>::FINGERPRINT,
+            HashMap::<u32, u64>::FINGERPRINT
+        );
+        assert_ne!(
+            BTreeMap::<u32, bool>::FINGERPRINT,
+            BTreeMap::<u64, bool>::FINGERPRINT
+        );
+        assert_ne!(HashSet::<u8>::FINGERPRINT, HashSet::<u16>::FINGERPRINT);
+        assert_ne!(BTreeSet::<u8>::FINGERPRINT, BTreeSet::<i8>::FINGERPRINT);
+        assert_ne!(VecDeque::<u8>::FINGERPRINT, VecDeque::<u16>::FINGERPRINT);
+        assert_ne!(<(u8, u16)>::FINGERPRINT, <(u8, u32)>::FINGERPRINT);
+        assert_ne!(<(u8, u16)>::FINGERPRINT, <(u16, u8)>::FINGERPRINT);
+        assert_ne!(<(u8,)>::FINGERPRINT, <(u8, ())>::FINGERPRINT);
+        assert_ne!(Box::<u8>::FINGERPRINT, Box::<u16>::FINGERPRINT);
+        assert_ne!(Vec::<Arc<u8>>::FINGERPRINT, Vec::<Arc<u16>>::FINGERPRINT);
+        assert_ne!(HashSet::<u8>::FINGERPRINT, BTreeSet::<u8>::FINGERPRINT);
+    }
+
+    #[test]
+    fn transparent() {
+        assert_eq!(Box::<u32>::FINGERPRINT, u32::FINGERPRINT);
+        assert_eq!(Rc::<str>::FINGERPRINT, String::FINGERPRINT);
+        assert_eq!(Cow::<[u8]>::FINGERPRINT, Vec::<u8>::FINGERPRINT);
+    }
+}
@@ -0,0 +1,46 @@
+use crate::*;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+pub type SettingsOf<A> = <<A as Action>::Shared as SharedState>::Settings;
+
+/// Game-defined options chosen by the lobby before a session starts, e.g. map, side or draft picks.
+pub trait LobbySettings: Message + Default + PartialEq + Schema {
+    /// Combine each seat's vote, in lobby order, into the settings the session starts with.
+    /// Defaults to the most popular vote, with ties going to whichever was cast first.
+    fn tally(votes: &[Option<Self>]) -> Self {
+        let votes: Vec<&Self> = votes.iter().flatten().collect();
+        votes
+            .iter()
+            .max_by_key(|vote| {
+                let count = votes.iter().filter(|v| v == vote).count();
+                let first = votes.iter().position(|v| v == *vote).unwrap_or_default();
+                (count, core::cmp::Reverse(first))
+            })
+            .map(|vote| (*vote).clone())
+            .unwrap_or_default()
+    }
+}
+
+impl LobbySettings for () {}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+pub struct LobbyMember<T: LobbySettings> {
+    pub index: u64,
+    pub name: String,
+    pub accepted: bool,
+    pub vote: Option<T>,
+}
+
+/// Lobby state sent to each member while waiting for everyone to accept
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+pub struct LobbyInfo<T: LobbySettings> {
+    pub members: Vec<LobbyMember<T>>,
+    /// Settings the session would start with if it started now
+    pub settings: T,
+    pub index: u64,
+    /// Hash of the server's seed when the queue is provably fair, see [`crate::fair`].
+    /// Known before accepting, so contributed entropy cannot be answered with a new seed.
+    pub commitment: Option<[u8; 32]>,
+}
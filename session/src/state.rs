@@ -3,17 +3,18 @@ use hashbrown::HashMap;
 use std::{borrow::Borrow, fmt::Debug, hash::Hash};
 
 pub trait UserState: 'static + Send + Sync + Clone + Debug {
-    type Info: Message;
+    type Info: Message + Schema + Diff;
     type Shared: SharedState;
     fn info<S: AsState<User = Self>>(index: S::Index, state: &S) -> HashMap<u64, Self::Info>;
     fn init(shared: &mut Self::Shared, users: usize) -> Vec<Self>;
 }
 
 pub trait SharedState: 'static + Send + Sync + Clone + Debug {
-    type Info: Message;
+    type Info: Message + Schema + Diff;
     type User: UserState;
+    type Settings: LobbySettings;
     fn info<S: AsState<Shared = Self>>(index: S::Index, state: &S) -> Self::Info;
-    fn init(seed: [u8; 32]) -> Self;
+    fn init(seed: [u8; 32], settings: Self::Settings) -> Self;
 }
 
 pub trait AsState {
@@ -28,3 +29,30 @@ pub trait AsState {
     fn shared_mut(&mut self) -> Option<impl AsMut<Self::Shared>>;
     fn indices(&self) -> impl Iterator<Item = u64>;
 }
+
+impl<S: AsState> AsState for &mut S {
+    type Shared = S::Shared;
+    type User = S::User;
+    type Index = S::Index;
+    fn index_matches(&self, i: u64, index: Self::Index) -> bool {
+        (**self).index_matches(i, index)
+    }
+    fn user(&self, i: u64) -> Option<impl Borrow<Self::User>> {
+        (**self).user(i)
+    }
+    fn users(&self) -> impl Iterator<Item = (u64, impl Borrow<Self::User>)> {
+        (**self).users()
+    }
+    fn user_mut(&mut self, i: u64) -> Option<impl AsMut<Self::User>> {
+        (**self).user_mut(i)
+    }
+    fn shared(&self) -> impl Borrow<Self::Shared> {
+        (**self).shared()
+    }
+    fn shared_mut(&mut self) -> Option<impl AsMut<Self::Shared>> {
+        (**self).shared_mut()
+    }
+    fn indices(&self) -> impl Iterator<Item = u64> {
+        (**self).indices()
+    }
+}
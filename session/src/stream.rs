@@ -0,0 +1,70 @@
+use crate::protocol::ProtocolError;
+
+/// Length of a stream frame header: body length (u32 LE) then tag (u8)
+pub const STREAM_HEADER_LEN: usize = 5;
+
+/// Largest frame body accepted from a stream
+pub const MAX_STREAM_FRAME: usize = 1 << 20;
+
+const MESSAGE: u8 = 0;
+const PING: u8 = 1;
+const PONG: u8 = 2;
+const CLOSE: u8 = 3;
+
+/// A frame on a length-prefixed byte stream such as raw TCP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StreamFrame {
+    /// An envelope, as produced by [`crate::protocol::encode`]
+    Message(Vec<u8>),
+    Ping,
+    Pong,
+    Close {
+        code: u16,
+        reason: String,
+    },
+}
+
+impl StreamFrame {
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, body) = match self {
+            Self::Message(bytes) => (MESSAGE, bytes.clone()),
+            Self::Ping => (PING, Vec::new()),
+            Self::Pong => (PONG, Vec::new()),
+            Self::Close { code, reason } => {
+                let mut body = code.to_le_bytes().to_vec();
+                body.extend_from_slice(reason.as_bytes());
+                (CLOSE, body)
+            }
+        };
+        let mut bytes = Vec::with_capacity(STREAM_HEADER_LEN + body.len());
+        bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes.push(tag);
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+    /// Read a header into the frame's tag and body length
+    pub fn header(header: [u8; STREAM_HEADER_LEN]) -> Result<(u8, usize), ProtocolError> {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if len > MAX_STREAM_FRAME {
+            return Err(ProtocolError::TooLarge(len));
+        }
+        Ok((header[4], len))
+    }
+    pub fn decode(tag: u8, body: Vec<u8>) -> Result<Self, ProtocolError> {
+        Ok(match tag {
+            MESSAGE => Self::Message(body),
+            PING => Self::Ping,
+            PONG => Self::Pong,
+            CLOSE => {
+                let [lo, hi, reason @ ..] = body.as_slice() else {
+                    return Err(ProtocolError::Truncated);
+                };
+                Self::Close {
+                    code: u16::from_le_bytes([*lo, *hi]),
+                    reason: String::from_utf8_lossy(reason).into_owned(),
+                }
+            }
+            tag => return Err(ProtocolError::UnknownFrame(tag)),
+        })
+    }
+}
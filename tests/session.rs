@@ -0,0 +1,358 @@
+use bevy::prelude::*;
+use ilium::{
+    client::native::Updates,
+    server::{
+        account::AccountMap,
+        app::Register,
+        backpressure::Backpressure,
+        ban::{Ban, Bans, IpBan},
+        history::MatchHistory,
+        matchmaking::{Accepted, new_session},
+        private::PrivateLobby,
+        queue::QueueComponent,
+        send::{Receivers, Sender},
+        tcp::TcpTransport,
+        transport::listen,
+    },
+    *,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Queue)]
+#[ilium(action = "Add")]
+pub enum Game {
+    #[ilium(size = "2")]
+    Duel,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Schema)]
+pub struct Add(u32);
+
+const LIMIT: u32 = 100;
+
+impl Action for Add {
+    type Shared = Board;
+    type User = Player;
+    type Error = String;
+    type Event = u32;
+    fn update<S: AsState<User = Player, Shared = Board>>(_state: S, _ctx: &mut Context<'_, Self>) {}
+    fn resolve<S: AsState<User = Player, Shared = Board>>(
+        self,
+        index: u64,
+        mut state: S,
+        _ctx: &mut Context<'_, Self>,
+    ) -> Result<(), String> {
+        if self.0 == 0 {
+            return Err("nothing to add".into());
+        }
+        state.user_mut(index).ok_or("no seat")?.as_mut().score += self.0;
+        let mut shared = state.shared_mut().ok_or("no session")?;
+        shared.as_mut().total += self.0;
+        // checked after changing the state, which the server has to undo
+        if shared.as_mut().total > LIMIT {
+            return Err("over the limit".into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, SharedState, Component)]
+#[ilium(user = "Player")]
+pub struct Board {
+    #[ilium(open)]
+    pub total: u32,
+}
+
+#[derive(Clone, Debug, Default, UserState, Component)]
+#[ilium(shared = "Board")]
+pub struct Player {
+    #[ilium(open)]
+    pub score: u32,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow, Component)]
+pub struct Guest {
+    pub rating: i64,
+}
+
+impl server::UserData for Guest {
+    type O = i64;
+    type DB = sqlx::Sqlite;
+    async fn query(
+        _pool: &sqlx::Pool<Self::DB>,
+        _account: &server::account::Account,
+    ) -> eyre::Result<Self> {
+        Ok(Self { rating: 0 })
+    }
+    fn matchmake_priority(&self) -> i64 {
+        self.rating
+    }
+    fn matchmake_valid(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+/// A server for `Game` accepting TCP clients, with its bevy app stepped by the caller
+async fn serve() -> eyre::Result<(App, String)> {
+    serve_with(Bans::default()).await
+}
+
+async fn serve_with(bans: Bans) -> eyre::Result<(App, String)> {
+    // guests never touch the database, so it is never connected to
+    let pool = sqlx::Pool::<sqlx::Sqlite>::connect_lazy("sqlite::memory:")?;
+    let (history, history_task) = MatchHistory::new::<Guest>(pool.clone());
+    let (sender, receivers) = GameSender::<Guest>::new(pool);
+    let backpressure = Backpressure::default();
+    let transport = TcpTransport::bind("127.0.0.1:0").await?;
+    let addr = transport.local_addr()?.to_string();
+    tokio::spawn(history_task);
+    tokio::spawn(listen(
+        transport,
+        sender.clone(),
+        backpressure.clone(),
+        bans.clone(),
+    ));
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(AccountMap::default())
+        .insert_resource(history)
+        .insert_resource(backpressure)
+        .insert_resource(bans)
+        .insert_resource(sender);
+    Game::register::<Guest>(&mut app);
+    receivers.insert(&mut app);
+    Ok((app, addr))
+}
+
+/// Step `app` until `until` finishes, failing after a few seconds
+async fn run<T>(app: &mut App, until: impl Future<Output = T> + Send + 'static) -> T
+where
+    T: Send + 'static,
+{
+    let task = tokio::spawn(until);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while !task.is_finished() {
+        assert!(tokio::time::Instant::now() < deadline, "timed out");
+        app.update();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    task.await.unwrap()
+}
+
+async fn next_state(
+    updates: &Updates<Game>,
+    matches: impl Fn(&StateInfo<Add>) -> bool,
+) -> StateInfo<Add> {
+    loop {
+        let state = updates.recv().await.expect("connection closed");
+        if matches(&state) {
+            return state;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn session_over_tcp() -> eyre::Result<()> {
+    let (mut app, addr) = serve().await?;
+    let players = async move {
+        let mut players = Vec::new();
+        for _ in 0..2 {
+            let (client, updates) =
+                GameNativeClient::connect_tcp(&addr, ClientToken::Guest).await?;
+            client.join(Game::Duel).await?;
+            players.push((client, updates));
+        }
+        for (client, updates) in &players {
+            next_state(updates, |s| matches!(s, StateInfo::Lobby(_))).await;
+            client.accept(Game::Duel).await?;
+        }
+        for (_, updates) in &players {
+            next_state(updates, |s| matches!(s, StateInfo::Session(_))).await;
+        }
+
+        let (client, updates) = &players[0];
+        let rejected = client.act(Game::Duel, Add(0)).await?;
+        let accepted = client.act(Game::Duel, Add(3)).await?;
+        let reply = updates.reply().await.expect("connection closed");
+        assert_eq!(reply.id, rejected);
+        assert_eq!(reply.result, Err("nothing to add".to_string()));
+        let reply = updates.reply().await.expect("connection closed");
+        assert_eq!(reply.id, accepted);
+        assert_eq!(reply.result, Ok(()));
+
+        // the other seat sees the action through a delta applied to its state
+        let StateInfo::Session(info) = next_state(&players[1].1, |s| match s {
+            StateInfo::Session(info) => info.shared.total == 3,
+            _ => false,
+        })
+        .await
+        else {
+            unreachable!()
+        };
+        assert_eq!(info.users.values().map(|u| u.score).sum::<u32>(), 3);
+
+        // spectators find the session by the id seats are sent
+        let (spectator, watching) =
+            GameNativeClient::connect_tcp(&addr, ClientToken::Guest).await?;
+        spectator.spectate(Game::Duel, info.session).await?;
+        let StateInfo::Session(seen) = next_state(&watching, |s| {
+            matches!(s, StateInfo::Session(_) | StateInfo::Closed)
+        })
+        .await
+        else {
+            panic!("spectated session was closed")
+        };
+        assert_eq!(seen.session, info.session);
+        assert_eq!(seen.shared.total, 3);
+        Ok::<_, eyre::Report>(())
+    };
+    run(&mut app, players).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rejected_action_rolls_back() -> eyre::Result<()> {
+    let (mut app, addr) = serve().await?;
+    let players = async move {
+        let mut players = Vec::new();
+        for _ in 0..2 {
+            let (client, updates) =
+                GameNativeClient::connect_tcp(&addr, ClientToken::Guest).await?;
+            client.join(Game::Duel).await?;
+            players.push((client, updates));
+        }
+        for (client, updates) in &players {
+            next_state(updates, |s| matches!(s, StateInfo::Lobby(_))).await;
+            client.accept(Game::Duel).await?;
+        }
+        for (_, updates) in &players {
+            next_state(updates, |s| matches!(s, StateInfo::Session(_))).await;
+        }
+        let (client, updates) = &players[0];
+        client.act(Game::Duel, Add(3)).await?;
+        let reply = updates.reply().await.expect("connection closed");
+        assert_eq!(reply.result, Ok(()));
+        Ok::<_, eyre::Report>(players)
+    };
+    let players = run(&mut app, players).await?;
+
+    let world = app.world_mut();
+    let changed = |world: &mut World| {
+        let mut ticks: Vec<_> = world
+            .query::<(Entity, Ref<Player>)>()
+            .iter(world)
+            .map(|(e, p)| (e, p.last_changed().get()))
+            .collect();
+        for (e, b) in world.query::<(Entity, Ref<Board>)>().iter(world) {
+            ticks.push((e, b.last_changed().get()));
+        }
+        ticks.sort();
+        ticks
+    };
+    let before = changed(world);
+    let reject = async move {
+        let (client, updates) = &players[0];
+        client.act(Game::Duel, Add(LIMIT)).await?;
+        let reply = updates.reply().await.expect("connection closed");
+        assert_eq!(reply.result, Err("over the limit".to_string()));
+        Ok::<_, eyre::Report>(())
+    };
+    run(&mut app, reject).await?;
+
+    // the rejected action's changes are gone, without marking anything as changed
+    let world = app.world_mut();
+    assert_eq!(changed(world), before);
+    let board = world.query::<&Board>().single(world)?;
+    assert_eq!(board.total, 3);
+    let mut scores: Vec<_> = world
+        .query::<&Player>()
+        .iter(world)
+        .map(|p| p.score)
+        .collect();
+    scores.sort();
+    assert_eq!(scores, [0, 3]);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn banned_over_tcp() -> eyre::Result<()> {
+    let bans = Bans::default();
+    bans.set(vec![IpBan {
+        range: "127.0.0.0/8".parse()?,
+        ban: Ban {
+            reason: "cheating".into(),
+            expires: None,
+        },
+    }]);
+    let (mut app, addr) = serve_with(bans).await?;
+    let connect = async move {
+        match GameNativeClient::connect_tcp(&addr, ClientToken::Guest).await {
+            Ok(_) => panic!("a banned client was let in"),
+            Err(e) => e.downcast::<client::native::Rejected>().map(|r| r.0),
+        }
+    };
+    assert_eq!(run(&mut app, connect).await?, "cheating");
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn private_member_leaves_while_starting() -> eyre::Result<()> {
+    let (mut app, addr) = serve().await?;
+    let lobby = async move {
+        let (host, host_updates) = GameNativeClient::connect_tcp(&addr, ClientToken::Guest).await?;
+        host.create(Game::Duel).await?;
+        let StateInfo::Private { code, .. } =
+            next_state(&host_updates, |s| matches!(s, StateInfo::Private { .. })).await
+        else {
+            unreachable!()
+        };
+        let (guest, guest_updates) =
+            GameNativeClient::connect_tcp(&addr, ClientToken::Guest).await?;
+        guest.join_code(Game::Duel, code).await?;
+        next_state(&host_updates, |s| {
+            matches!(s, StateInfo::Private { members: 2, .. })
+        })
+        .await;
+        Ok::<_, eyre::Report>((host, host_updates, guest, guest_updates))
+    };
+    let (_host, host_updates, _guest, _guest_updates) = run(&mut app, lobby).await?;
+
+    // start the lobby as the host would, then lose the guest before the session starts
+    let world = app.world_mut();
+    let (entity, private) = world.query::<(Entity, &PrivateLobby)>().single(world)?;
+    let started = private.clone();
+    let lobby = <DuelComponent as QueueComponent>::Lobby::try_from(&started.members[..])?;
+    for member in started.members.iter() {
+        world.entity_mut(*member).insert(Accepted);
+    }
+    let mut commands = world.commands();
+    let mut ec = commands.entity(entity);
+    ec.remove::<DuelComponent>();
+    new_session::<DuelComponent>(&mut ec, lobby);
+    world.flush();
+    world.despawn(started.members[1]);
+
+    // the host is back in its lobby, alone and able to invite someone else
+    let back = async move {
+        next_state(&host_updates, |s| {
+            matches!(
+                s,
+                StateInfo::Private {
+                    members: 1,
+                    host: true,
+                    ..
+                }
+            )
+        })
+        .await;
+    };
+    run(&mut app, back).await;
+    let world = app.world_mut();
+    let private = world
+        .query_filtered::<&PrivateLobby, With<DuelComponent>>()
+        .single(world)?;
+    assert_eq!(private.code, started.code);
+    assert_eq!(private.members, [started.members[0]]);
+    assert!(!world.entity(private.members[0]).contains::<Accepted>());
+    Ok(())
+}